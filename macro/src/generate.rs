@@ -4,7 +4,18 @@ use proc_macro2::{Ident, Span, TokenStream};
 use quote::{quote, quote_spanned};
 
 pub fn generate(syntax: Vec<(Syntax, Span)>) -> TokenStream {
-    let mut tokens = quote!(::bitcoin_script::Script::new(::bitcoin_script::function_name!()));
+    let mut tokens = if cfg!(feature = "script-debug") {
+        quote!(::bitcoin_script::Script::new(&format!(
+            "{} [{}:{}]",
+            ::bitcoin_script::function_name!(),
+            file!(),
+            line!()
+        )))
+    } else {
+        quote!(::bitcoin_script::Script::new(
+            ::bitcoin_script::function_name!()
+        ))
+    };
 
     for (item, span) in syntax {
         let push = match item {
@@ -12,6 +23,7 @@ pub fn generate(syntax: Vec<(Syntax, Span)>) -> TokenStream {
             Syntax::Bytes(bytes) => generate_bytes(bytes, span),
             Syntax::Int(int) => generate_int(int, span),
             Syntax::Escape(expression) => generate_escape(expression, span),
+            Syntax::Doc(doc) => generate_doc(doc, span),
         };
         tokens.extend(push);
     }
@@ -43,3 +55,27 @@ fn generate_escape(expression: TokenStream, span: Span) -> TokenStream {
             .push_expression(#expression)
     )
 }
+
+fn generate_doc(doc: TokenStream, span: Span) -> TokenStream {
+    quote_spanned!(span=>.add_doc(#doc))
+}
+
+// Wraps the body of a function that returns a script so that its /// doc comments are put
+// in front of the doc of the returned script.
+pub fn generate_script_doc(
+    head: TokenStream,
+    docs: Vec<TokenStream>,
+    body: TokenStream,
+) -> TokenStream {
+    quote!(
+        #head {
+            let mut script: ::bitcoin_script::Script = (move || { #body })();
+            let inner_doc = script.doc.take();
+            script = script #(.add_doc(#docs))*;
+            if let Some(inner_doc) = inner_doc {
+                script = script.add_doc(&inner_doc);
+            }
+            script
+        }
+    )
+}
@@ -1,8 +1,8 @@
 mod generate;
 mod parse;
 
-use generate::generate;
-use parse::parse;
+use generate::{generate, generate_script_doc};
+use parse::{parse, parse_documented_fn};
 use proc_macro::TokenStream;
 use proc_macro_error::{proc_macro_error, set_dummy};
 use quote::quote;
@@ -13,3 +13,11 @@ pub fn script(tokens: TokenStream) -> TokenStream {
     set_dummy(quote!((::bitcoin::Script::new())));
     generate(parse(tokens.into())).into()
 }
+
+// Attaches the /// doc comments of a function to the script it returns.
+#[proc_macro_attribute]
+#[proc_macro_error]
+pub fn script_doc(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    let (head, docs, body) = parse_documented_fn(item.into());
+    generate_script_doc(head, docs, body).into()
+}
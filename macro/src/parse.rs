@@ -16,6 +16,8 @@ pub enum Syntax {
     Escape(TokenStream),
     Bytes(Vec<u8>),
     Int(i64),
+    // String literal of an inner doc comment
+    Doc(TokenStream),
 }
 
 macro_rules! emit_error {
@@ -24,7 +26,10 @@ macro_rules! emit_error {
         proc_macro_error::emit_error!($span, $($message),*);
 
         #[cfg(test)]
-        panic!($($message),*);
+        {
+            let _ = $span;
+            panic!($($message),*);
+        }
 
         #[allow(unreachable_code)]
         {
@@ -39,7 +44,10 @@ macro_rules! abort {
         proc_macro_error::abort!($span, $($message),*);
 
         #[cfg(test)]
-        panic!($($message),*);
+        {
+            let _ = $span;
+            panic!($($message),*);
+        }
     }}
 }
 
@@ -353,6 +361,14 @@ pub fn parse(tokens: TokenStream) -> Vec<(Syntax, Span)> {
             (Ident(_), "for") => parse_for_loop(token, &mut tokens),
             // Wrap if-else statements such that they return a Vec<ScriptBuf>
             (Ident(_), "if") => parse_if(token, &mut tokens),
+            // Wrap match statements such that they return the script of the taken arm
+            (Ident(_), "match") => parse_match(token, &mut tokens),
+            // Push every script of a collection
+            (Ident(_), "spread")
+                if matches!(tokens.peek(), Some(Punct(punct)) if punct.as_char() == '!') =>
+            {
+                parse_spread(token, &mut tokens)
+            }
             // Replace DEBUG with OP_RESERVED
             (Ident(_), "DEBUG") => {
                 (Syntax::Opcode(OP_RESERVED), token.span())
@@ -377,6 +393,9 @@ pub fn parse(tokens: TokenStream) -> Vec<(Syntax, Span)> {
             // '<', start of escape (parse until first '>')
             (Punct(_), "<") => parse_escape(token, &mut tokens),
 
+            // '#' start of a //! doc comment or a #[cfg(...)] attribute on a script block
+            (Punct(_), "#") => parse_attribute(token, &mut tokens),
+
             // '~' start of escape (parse until the next '~') ignores '<' and '>'
             (Punct(_), "~") => parse_escape_extra(token, &mut tokens),
 
@@ -468,6 +487,218 @@ where
     (Syntax::Escape(quote! { { #escape } }), token.span())
 }
 
+fn parse_match<T>(token: TokenTree, tokens: &mut T) -> (Syntax, Span)
+where
+    T: Iterator<Item = TokenTree>,
+{
+    let mut escape = quote! {
+        let mut script_var = bitcoin_script::Script::new("match");
+    };
+    escape.extend(std::iter::once(token.clone()));
+
+    for match_token in tokens.by_ref() {
+        match match_token {
+            Group(block) if block.delimiter() == Delimiter::Brace => {
+                let arms = parse_match_arms(block);
+                escape.extend(quote! {
+                    {
+                        #arms
+                    }
+                    script_var
+                });
+                break;
+            }
+            _ => {
+                escape.extend(std::iter::once(match_token));
+                continue;
+            }
+        };
+    }
+
+    (Syntax::Escape(quote! { { #escape } }), token.span())
+}
+
+fn parse_match_arms(block: proc_macro2::Group) -> TokenStream {
+    let mut arms = TokenStream::new();
+    let mut tokens = block.stream().into_iter().peekable();
+
+    while let Some(token) = tokens.next() {
+        let is_arrow = matches!(&token, Punct(punct) if punct.as_char() == '=')
+            && matches!(tokens.peek(), Some(Punct(punct)) if punct.as_char() == '>');
+        arms.extend(std::iter::once(token));
+        if !is_arrow {
+            continue;
+        }
+        arms.extend(tokens.next());
+
+        // Every arm body is a script block that is pushed if the arm is taken
+        match tokens.next() {
+            Some(Group(body)) if body.delimiter() == Delimiter::Brace => {
+                let inner_block = body.stream();
+                arms.extend(quote! {
+                    {
+                        script_var = script_var.push_env_script(script! {
+                            #inner_block
+                        });
+                    }
+                });
+            }
+            other => {
+                #[allow(unused_variables)]
+                let span = other.map_or(block.span(), |token| token.span());
+                abort!(span, "expected block as match arm body");
+            }
+        }
+
+        // Skip the optional trailing comma
+        if matches!(tokens.peek(), Some(Punct(punct)) if punct.as_char() == ',') {
+            tokens.next();
+        }
+    }
+    arms
+}
+
+fn parse_spread<T>(token: TokenTree, tokens: &mut T) -> (Syntax, Span)
+where
+    T: Iterator<Item = TokenTree>,
+{
+    // Skip the '!'
+    tokens.next();
+    match tokens.next() {
+        Some(Group(collection)) if collection.delimiter() == Delimiter::Parenthesis => {
+            let collection = collection.stream();
+            let escape = quote! {
+                {
+                    let mut script_var = bitcoin_script::Script::new("spread");
+                    for spread_script in #collection {
+                        script_var = script_var.push_env_script(spread_script);
+                    }
+                    script_var
+                }
+            };
+            (Syntax::Escape(escape), token.span())
+        }
+        other => {
+            #[allow(unused_variables)]
+            let span = other.map_or(token.span(), |token| token.span());
+            abort!(span, "expected parentheses after spread!");
+        }
+    }
+}
+
+// Splits a function into everything in front of its body, the string literals of its
+// /// doc comments and its body.
+pub fn parse_documented_fn(item: TokenStream) -> (TokenStream, Vec<TokenStream>, TokenStream) {
+    let mut head: Vec<TokenTree> = item.into_iter().collect();
+    let body = match head.pop() {
+        Some(Group(body)) if body.delimiter() == Delimiter::Brace => body.stream(),
+        token => abort!(
+            token.map_or(Span::call_site(), |token| token.span()),
+            "expected a function"
+        ),
+    };
+    let mut docs = Vec::new();
+    for (token, attribute) in head.iter().zip(head.iter().skip(1)) {
+        match (token, attribute) {
+            (Punct(punct), Group(attribute))
+                if punct.as_char() == '#' && attribute.delimiter() == Delimiter::Bracket =>
+            {
+                let attribute_tokens: Vec<TokenTree> = attribute.stream().into_iter().collect();
+                if let [Ident(ident), Punct(punct), doc @ Literal(_)] = attribute_tokens.as_slice()
+                {
+                    if ident == "doc" && punct.as_char() == '=' {
+                        docs.push(TokenStream::from(doc.clone()));
+                    }
+                }
+            }
+            (Ident(ident), _) if ident == "fn" => break,
+            _ => (),
+        }
+    }
+    (head.into_iter().collect(), docs, body)
+}
+
+fn parse_attribute<T>(token: TokenTree, tokens: &mut Peekable<T>) -> (Syntax, Span)
+where
+    T: Iterator<Item = TokenTree>,
+{
+    // Inner doc comments arrive as #![doc = "..."]
+    if matches!(tokens.peek(), Some(Punct(punct)) if punct.as_char() == '!') {
+        tokens.next();
+        return parse_doc(token, tokens);
+    }
+    parse_cfg(token, tokens)
+}
+
+fn parse_doc<T>(token: TokenTree, tokens: &mut T) -> (Syntax, Span)
+where
+    T: Iterator<Item = TokenTree>,
+{
+    match tokens.next() {
+        Some(Group(attribute)) if attribute.delimiter() == Delimiter::Bracket => {
+            let mut attribute_tokens = attribute.stream().into_iter();
+            match (
+                attribute_tokens.next(),
+                attribute_tokens.next(),
+                attribute_tokens.next(),
+            ) {
+                (Some(Ident(ident)), Some(Punct(punct)), Some(doc @ Literal(_)))
+                    if ident == "doc" && punct.as_char() == '=' =>
+                {
+                    (Syntax::Doc(TokenStream::from(doc)), token.span())
+                }
+                _ => abort!(attribute.span(), "expected doc comment"),
+            }
+        }
+        _ => abort!(token.span(), "expected doc comment"),
+    }
+}
+
+fn parse_cfg<T>(token: TokenTree, tokens: &mut T) -> (Syntax, Span)
+where
+    T: Iterator<Item = TokenTree>,
+{
+    // The attribute has to be of the form #[cfg(<predicate>)]
+    let predicate = match tokens.next() {
+        Some(Group(attribute)) if attribute.delimiter() == Delimiter::Bracket => {
+            let mut attribute_tokens = attribute.stream().into_iter();
+            match (attribute_tokens.next(), attribute_tokens.next()) {
+                (Some(Ident(ident)), Some(Group(predicate)))
+                    if ident == "cfg" && predicate.delimiter() == Delimiter::Parenthesis =>
+                {
+                    predicate.stream()
+                }
+                _ => abort!(attribute.span(), "expected cfg attribute"),
+            }
+        }
+        _ => abort!(token.span(), "expected cfg attribute"),
+    };
+
+    // The script block is only compiled in if the predicate holds
+    match tokens.next() {
+        Some(Group(block)) if block.delimiter() == Delimiter::Brace => {
+            let inner_block = block.stream();
+            let escape = quote! {
+                {
+                    if cfg!(#predicate) {
+                        script! {
+                            #inner_block
+                        }
+                    } else {
+                        bitcoin_script::Script::new("")
+                    }
+                }
+            };
+            (Syntax::Escape(escape), token.span())
+        }
+        other => {
+            #[allow(unused_variables)]
+            let span = other.map_or(token.span(), |token| token.span());
+            abort!(span, "expected block after cfg attribute");
+        }
+    }
+}
+
 fn parse_escape<T>(token: TokenTree, tokens: &mut T) -> (Syntax, Span)
 where
     T: Iterator<Item = TokenTree>,
@@ -772,6 +1003,120 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_match() {
+        let syntax = parse(quote!(OP_DUP match x { 0 => { OP_1 }, _ => { OP_2 } } OP_DROP));
+
+        assert_eq!(syntax.len(), 3);
+        assert!(matches!(syntax[1].0, Syntax::Escape(_)));
+        assert!(matches!(syntax[2].0, Syntax::Opcode(OP_DROP)));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected block as match arm body")]
+    fn parse_match_without_block() {
+        parse(quote!(match x {
+            0 => OP_1,
+            _ => {
+                OP_2
+            }
+        }));
+    }
+
+    #[test]
+    fn parse_cfg() {
+        let syntax = parse(quote!(OP_1 #[cfg(feature = "script-debug")] { OP_DUP OP_DROP } OP_2));
+
+        assert_eq!(syntax.len(), 3);
+        assert!(matches!(syntax[1].0, Syntax::Escape(_)));
+        assert!(matches!(syntax[2].0, Syntax::Opcode(OP_PUSHNUM_2)));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected cfg attribute")]
+    fn parse_cfg_invalid_attribute() {
+        parse(quote!(
+            #[inline]
+            {
+                OP_1
+            }
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected block after cfg attribute")]
+    fn parse_cfg_without_block() {
+        parse(quote!(
+            #[cfg(test)]
+            OP_1
+        ));
+    }
+
+    #[test]
+    fn parse_doc() {
+        let syntax = parse(quote!(
+            //! Adds two numbers
+            OP_ADD
+        ));
+
+        assert_eq!(syntax.len(), 2);
+        if let Syntax::Doc(doc) = &syntax[0].0 {
+            assert!(doc.to_string().contains(" Adds two numbers"));
+        } else {
+            panic!("Expected Syntax::Doc, got {:?}", syntax[0].0);
+        }
+    }
+
+    #[test]
+    fn parse_documented_fn() {
+        let (head, docs, body) = super::parse_documented_fn(quote!(
+            /// Adds two numbers
+            #[inline]
+            fn add() -> Script {
+                script! { OP_ADD }
+            }
+        ));
+
+        assert_eq!(docs.len(), 1);
+        assert!(docs[0].to_string().contains(" Adds two numbers"));
+        assert!(head.to_string().contains("fn add"));
+        assert!(body.to_string().contains("OP_ADD"));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a function")]
+    fn parse_documented_fn_without_body() {
+        super::parse_documented_fn(quote!(
+            struct Add;
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected doc comment")]
+    fn parse_invalid_inner_attribute() {
+        parse(quote!(#![allow(unused)] OP_ADD));
+    }
+
+    #[test]
+    fn parse_spread() {
+        let syntax = parse(quote!(OP_1 spread!(scripts) spread OP_2));
+
+        assert_eq!(syntax.len(), 4);
+        assert!(matches!(syntax[1].0, Syntax::Escape(_)));
+        // Without '!' spread is a function call
+        if let Syntax::Escape(escape) = &syntax[2].0 {
+            assert_eq!(escape.to_string(), "spread ()");
+        } else {
+            panic!("Expected Syntax::Escape, got {:?}", syntax[2].0);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "expected parentheses after spread!")]
+    fn parse_spread_without_parentheses() {
+        parse(quote!(spread! { scripts }));
+    }
+
     #[test]
     fn parse_hex() {
         let syntax = parse(quote!(OP_CHECKSIG 0x123456789abcde));
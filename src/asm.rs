@@ -0,0 +1,176 @@
+use crate::builder::{Block, StructuredScript};
+use bitcoin::blockdata::opcodes::Opcode;
+use bitcoin::hex::FromHex;
+use bitcoin::opcodes::all::*;
+use bitcoin::script::PushBytesBuf;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+lazy_static::lazy_static! {
+    // Opcode names as printed by to_asm_string plus the common aliases.
+    static ref OPCODES: HashMap<String, Opcode> = {
+        let mut opcodes: HashMap<String, Opcode> = (0..=255u8)
+            .map(|byte| (format!("{:?}", Opcode::from(byte)), Opcode::from(byte)))
+            .collect();
+        opcodes.insert("OP_0".to_string(), OP_PUSHBYTES_0);
+        opcodes.insert("OP_FALSE".to_string(), OP_PUSHBYTES_0);
+        opcodes.insert("OP_TRUE".to_string(), OP_PUSHNUM_1);
+        for n in 1..=16u8 {
+            opcodes.insert(format!("OP_{}", n), Opcode::from(OP_PUSHNUM_1.to_u8() + n - 1));
+        }
+        opcodes
+    };
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptParseError {
+    // Byte offset of the token in the parsed string.
+    pub position: usize,
+    pub token: String,
+    pub reason: String,
+}
+
+impl fmt::Display for ScriptParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid token \"{}\" at position {}: {}",
+            self.token, self.position, self.reason
+        )
+    }
+}
+
+impl std::error::Error for ScriptParseError {}
+
+impl StructuredScript {
+    // The asm of the compiled script where the doc of every script, including the called
+    // ones, is printed as `// <doc>` lines in front of its instructions.
+    pub fn to_asm_string(&self) -> String {
+        let mut lines = Vec::new();
+        let mut line = String::new();
+        self.write_asm(&mut lines, &mut line);
+        finish_line(&mut lines, &mut line);
+        lines.join("\n")
+    }
+
+    fn write_asm(&self, lines: &mut Vec<String>, line: &mut String) {
+        if let Some(doc) = &self.doc {
+            finish_line(lines, line);
+            for doc_line in doc.lines() {
+                lines.push(format!("// {}", doc_line).trim_end().to_string());
+            }
+        }
+        for block in &self.blocks {
+            match block {
+                Block::Call(id) => self.get_structured_script(id).write_asm(lines, line),
+                Block::Script(script_buf) if !script_buf.is_empty() => {
+                    if !line.is_empty() {
+                        line.push(' ');
+                    }
+                    line.push_str(&script_buf.to_asm_string());
+                }
+                Block::Script(_) => (),
+            }
+        }
+        // The instructions after a documented script start on a new line
+        if self.doc.is_some() {
+            finish_line(lines, line);
+        }
+    }
+}
+
+fn finish_line(lines: &mut Vec<String>, line: &mut String) {
+    if !line.is_empty() {
+        lines.push(std::mem::take(line));
+    }
+}
+
+// Parses the format of to_asm_string: opcode names separated by whitespace where every push
+// opcode is followed by its data in hex. A hex token without a push opcode is pushed as is.
+// Pushes are re-encoded minimally, e.g. OP_PUSHBYTES_1 05 becomes OP_PUSHNUM_5. Everything
+// from // to the end of the line is a comment.
+impl TryFrom<&str> for StructuredScript {
+    type Error = ScriptParseError;
+
+    fn try_from(asm: &str) -> Result<Self, Self::Error> {
+        let mut script = StructuredScript::new("asm");
+        let mut tokens = tokens(asm);
+        while let Some((position, token)) = tokens.next() {
+            if let Some(&opcode) = OPCODES.get(token) {
+                let expected_len = match opcode {
+                    OP_PUSHDATA1 | OP_PUSHDATA2 | OP_PUSHDATA4 => None,
+                    _ if opcode.to_u8() > 0 && opcode.to_u8() <= OP_PUSHBYTES_75.to_u8() => {
+                        Some(opcode.to_u8() as usize)
+                    }
+                    _ => {
+                        script = script.push_opcode(opcode);
+                        continue;
+                    }
+                };
+                let (data_position, data_token) = tokens.next().ok_or(ScriptParseError {
+                    position,
+                    token: token.to_string(),
+                    reason: "missing push data".to_string(),
+                })?;
+                let data = parse_hex(data_position, data_token)?;
+                if let Some(expected_len) = expected_len {
+                    if data.len() != expected_len {
+                        return Err(ScriptParseError {
+                            position: data_position,
+                            token: data_token.to_string(),
+                            reason: format!(
+                                "expected {} bytes after {}, got {}",
+                                expected_len,
+                                token,
+                                data.len()
+                            ),
+                        });
+                    }
+                }
+                script = push_minimal(script, data);
+            } else if token.starts_with("OP_") {
+                return Err(ScriptParseError {
+                    position,
+                    token: token.to_string(),
+                    reason: "unknown opcode".to_string(),
+                });
+            } else {
+                script = push_minimal(script, parse_hex(position, token)?);
+            }
+        }
+        Ok(script)
+    }
+}
+
+// Splits on whitespace, skips comments and yields every token with its byte offset.
+fn tokens(asm: &str) -> impl Iterator<Item = (usize, &str)> {
+    asm.lines()
+        .flat_map(|line| {
+            line.split("//")
+                .next()
+                .unwrap_or_default()
+                .split_whitespace()
+        })
+        .map(move |token| (token.as_ptr() as usize - asm.as_ptr() as usize, token))
+}
+
+// Pushes data with the shortest encoding, which compile requires.
+fn push_minimal(script: StructuredScript, data: PushBytesBuf) -> StructuredScript {
+    match data.as_bytes() {
+        [] => script.push_opcode(OP_PUSHBYTES_0),
+        [n @ 1..=16] => script.push_opcode(Opcode::from(OP_PUSHNUM_1.to_u8() + n - 1)),
+        [0x81] => script.push_opcode(OP_PUSHNUM_NEG1),
+        _ => script.push_slice(data),
+    }
+}
+
+fn parse_hex(position: usize, token: &str) -> Result<PushBytesBuf, ScriptParseError> {
+    let error = |reason: &str| ScriptParseError {
+        position,
+        token: token.to_string(),
+        reason: reason.to_string(),
+    };
+    let data = Vec::from_hex(token).map_err(|_| error("invalid hex"))?;
+    PushBytesBuf::try_from(data).map_err(|_| error("push data too large"))
+}
@@ -0,0 +1,174 @@
+use crate::builder::StructuredScript;
+use crate::validation::{ScriptContext, MAX_OPS_PER_SCRIPT};
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::opcodes::all::*;
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey, XOnlyPublicKey};
+use std::fmt;
+
+lazy_static::lazy_static! {
+    // Public keys of the secret keys 1 to 16, which anyone can sign for.
+    static ref WEAK_KEYS: Vec<PublicKey> = {
+        let secp = Secp256k1::signing_only();
+        (1..=16u8)
+            .map(|i| {
+                let mut secret = [0u8; 32];
+                secret[31] = i;
+                PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&secret).unwrap())
+            })
+            .collect()
+    };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FindingSeverity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for FindingSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FindingSeverity::Info => write!(f, "info"),
+            FindingSeverity::Warning => write!(f, "warning"),
+            FindingSeverity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FindingKind {
+    // An OP_IF or OP_NOTIF inside of another one.
+    NestedIfs,
+    // A public key whose secret key is known.
+    HardcodedKey,
+    // The opcode count is close to or above MAX_OPS_PER_SCRIPT. Not checked for tapscript,
+    // which has no opcode limit.
+    ExcessiveOps,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditFinding {
+    pub kind: FindingKind,
+    pub severity: FindingSeverity,
+    pub message: String,
+    pub byte_offset: usize,
+}
+
+impl fmt::Display for AuditFinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at byte {}: {}",
+            self.severity, self.byte_offset, self.message
+        )
+    }
+}
+
+// Looks for patterns in the compiled script that are valid but likely unintended.
+#[derive(Clone, Debug)]
+pub struct ScriptAudit {
+    pub context: ScriptContext,
+    // Scripts with at least this many opcodes get an ExcessiveOps warning. Above
+    // MAX_OPS_PER_SCRIPT it is an error.
+    pub max_ops_warning: usize,
+}
+
+impl Default for ScriptAudit {
+    fn default() -> Self {
+        ScriptAudit {
+            context: ScriptContext::P2WSH,
+            max_ops_warning: MAX_OPS_PER_SCRIPT * 9 / 10,
+        }
+    }
+}
+
+impl ScriptAudit {
+    pub fn run(&self, script: &StructuredScript) -> Vec<AuditFinding> {
+        let compiled = script.clone().compile();
+        let mut findings = Vec::new();
+        let mut if_depth: usize = 0;
+        let mut op_count = 0;
+        // Position of the opcode that reached max_ops_warning
+        let mut excessive_ops_offset = None;
+        for (position, instruction) in compiled.instruction_indices().flatten() {
+            match instruction {
+                Instruction::PushBytes(push_bytes) => {
+                    if let Some(i) = weak_key_index(push_bytes.as_bytes()) {
+                        findings.push(AuditFinding {
+                            kind: FindingKind::HardcodedKey,
+                            severity: FindingSeverity::Error,
+                            message: format!(
+                                "public key of the secret key {} in {}",
+                                i + 1,
+                                script.debug_info(position)
+                            ),
+                            byte_offset: position,
+                        });
+                    }
+                }
+                Instruction::Op(opcode) => {
+                    if opcode.to_u8() > OP_PUSHNUM_16.to_u8() {
+                        op_count += 1;
+                        if op_count == self.max_ops_warning {
+                            excessive_ops_offset = Some(position);
+                        }
+                    }
+                    match opcode {
+                        OP_IF | OP_NOTIF => {
+                            if if_depth > 0 {
+                                findings.push(AuditFinding {
+                                    kind: FindingKind::NestedIfs,
+                                    severity: FindingSeverity::Info,
+                                    message: format!(
+                                        "{} at depth {} in {}",
+                                        opcode,
+                                        if_depth + 1,
+                                        script.debug_info(position)
+                                    ),
+                                    byte_offset: position,
+                                });
+                            }
+                            if_depth += 1;
+                        }
+                        OP_ENDIF => if_depth = if_depth.saturating_sub(1),
+                        _ => (),
+                    }
+                }
+            }
+        }
+        if let Some(byte_offset) =
+            excessive_ops_offset.filter(|_| self.context != ScriptContext::P2TR)
+        {
+            let severity = if op_count > MAX_OPS_PER_SCRIPT {
+                FindingSeverity::Error
+            } else {
+                FindingSeverity::Warning
+            };
+            findings.push(AuditFinding {
+                kind: FindingKind::ExcessiveOps,
+                severity,
+                message: format!("{} opcodes, the limit is {}", op_count, MAX_OPS_PER_SCRIPT),
+                byte_offset,
+            });
+        }
+        findings
+    }
+}
+
+// Index into WEAK_KEYS of a compressed, uncompressed or x-only public key.
+fn weak_key_index(data: &[u8]) -> Option<usize> {
+    match data.len() {
+        32 => {
+            let key = XOnlyPublicKey::from_slice(data).ok()?;
+            WEAK_KEYS
+                .iter()
+                .position(|weak_key| weak_key.x_only_public_key().0 == key)
+        }
+        33 | 65 => {
+            let key = PublicKey::from_slice(data).ok()?;
+            WEAK_KEYS.iter().position(|weak_key| *weak_key == key)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,123 @@
+use crate::builder::{Pushable, StructuredScript};
+use bitcoin::blockdata::opcodes::Opcode;
+use bitcoin::blockdata::script::{PushBytes, ScriptBuf};
+use std::ops::Deref;
+
+#[derive(Clone, Debug)]
+pub struct BudgetExceeded {
+    // Size the script would have had after the rejected push.
+    pub current_size: usize,
+    pub budget: usize,
+    // Debug identifier of the pushed script, or the asm of the pushed data and opcodes.
+    pub added_by: String,
+    // The script without the rejected push, so that building can go on.
+    pub script: Box<BudgetedScript>,
+}
+
+// A script that fails as soon as a push makes it larger than the budget. The analysis
+// methods of the inner script are available through Deref.
+#[derive(Clone, Debug)]
+pub struct BudgetedScript {
+    inner: StructuredScript,
+    budget: usize,
+}
+
+impl BudgetedScript {
+    pub fn new(debug_info: &str, budget: usize) -> Self {
+        BudgetedScript {
+            inner: StructuredScript::new(debug_info),
+            budget,
+        }
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    pub fn into_inner(self) -> StructuredScript {
+        self.inner
+    }
+
+    fn push_with(
+        self,
+        pushed_identifier: Option<String>,
+        push: impl FnOnce(StructuredScript) -> StructuredScript,
+    ) -> Result<BudgetedScript, BudgetExceeded> {
+        let old_len = self.inner.len();
+        // Pushing a script into an empty script returns the pushed script
+        let empty = self
+            .inner
+            .is_empty()
+            .then(|| (self.inner.debug_identifier.clone(), self.inner.doc.clone()));
+        let mut inner = push(self.inner);
+        if inner.len() <= self.budget {
+            return Ok(BudgetedScript {
+                inner,
+                budget: self.budget,
+            });
+        }
+        let added_by = pushed_identifier.unwrap_or_else(|| {
+            let compiled = inner.clone().compile();
+            ScriptBuf::from_bytes(compiled.as_bytes()[old_len..].to_vec()).to_asm_string()
+        });
+        let current_size = inner.len();
+        match empty {
+            Some((debug_identifier, doc)) => {
+                inner = StructuredScript::new(&debug_identifier);
+                inner.doc = doc;
+            }
+            None => inner.truncate(old_len),
+        }
+        Err(BudgetExceeded {
+            current_size,
+            budget: self.budget,
+            added_by,
+            script: Box::new(BudgetedScript {
+                inner,
+                budget: self.budget,
+            }),
+        })
+    }
+
+    pub fn push_opcode(self, data: Opcode) -> Result<BudgetedScript, BudgetExceeded> {
+        self.push_with(None, |script| script.push_opcode(data))
+    }
+
+    pub fn push_int(self, data: i64) -> Result<BudgetedScript, BudgetExceeded> {
+        self.push_with(None, |script| script.push_int(data))
+    }
+
+    pub fn push_slice<T: AsRef<PushBytes>>(
+        self,
+        data: T,
+    ) -> Result<BudgetedScript, BudgetExceeded> {
+        self.push_with(None, |script| script.push_slice(data))
+    }
+
+    pub fn push_script(self, data: ScriptBuf) -> Result<BudgetedScript, BudgetExceeded> {
+        self.push_with(None, |script| script.push_script(data))
+    }
+
+    pub fn push_env_script(self, data: StructuredScript) -> Result<BudgetedScript, BudgetExceeded> {
+        let pushed_identifier = Some(data.debug_identifier.clone());
+        self.push_with(pushed_identifier, |script| script.push_env_script(data))
+    }
+
+    pub fn push_expression<T: Pushable>(
+        self,
+        expression: T,
+    ) -> Result<BudgetedScript, BudgetExceeded> {
+        let pushed_identifier = expression.pushed_identifier();
+        self.push_with(pushed_identifier, |script| {
+            script.push_expression(expression)
+        })
+    }
+}
+
+impl Deref for BudgetedScript {
+    type Target = StructuredScript;
+
+    fn deref(&self) -> &StructuredScript {
+        &self.inner
+    }
+}
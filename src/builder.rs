@@ -1,15 +1,24 @@
 use bitcoin::blockdata::opcodes::Opcode;
 use bitcoin::blockdata::script::{Instruction, PushBytes, PushBytesBuf, ScriptBuf};
+use bitcoin::hashes::{sha256, siphash24, Hash as BitcoinHash, HashEngine};
+use bitcoin::opcodes::all::{
+    OP_DROP, OP_DUP, OP_ENDIF, OP_GREATERTHANOREQUAL, OP_IF, OP_LESSTHANOREQUAL, OP_NOTIF,
+    OP_PUSHDATA4, OP_VERIFY,
+};
 use bitcoin::opcodes::{OP_0, OP_TRUE};
 use bitcoin::script::write_scriptint;
-use bitcoin::Witness;
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::taproot::{TaprootBuilder, TaprootSpendInfo};
+use bitcoin::{Address, Network, Witness, XOnlyPublicKey};
 use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::hash::{DefaultHasher, Hash, Hasher};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Mul, MulAssign};
 
 #[derive(Clone, Debug, Hash)]
 pub enum Block {
-    Call(u64),
+    Call(ScriptId),
     Script(ScriptBuf),
 }
 
@@ -24,8 +33,13 @@ impl Block {
 pub struct StructuredScript {
     size: usize,
     pub debug_identifier: String,
-    pub blocks: Vec<Block>, //List?
-    script_map: HashMap<u64, StructuredScript>,
+    // Collected from //! comments in the script! macro.
+    pub doc: Option<String>,
+    pub blocks: Vec<Block>,
+    // End position of every block in blocks, used to find the block of a position with a
+    // binary search. It is out of sync if blocks was changed directly.
+    block_ends: Vec<usize>,
+    script_map: HashMap<ScriptId, StructuredScript>,
 }
 
 impl Hash for StructuredScript {
@@ -34,10 +48,35 @@ impl Hash for StructuredScript {
     }
 }
 
-fn calculate_hash<T: Hash>(t: &T) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    t.hash(&mut hasher);
-    hasher.finish()
+// Content address of a script in the script_map. It is the SipHash-2-4 with a zero key of
+// the blocks of the script, so it is the same in every process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ScriptId(pub u64);
+
+impl fmt::Display for ScriptId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+// A script block is serialized as the tag 0x00 followed by its length as u64 (little
+// endian) and its bytes, a call as the tag 0x01 followed by the id of the called script.
+fn calculate_hash(script: &StructuredScript) -> ScriptId {
+    let mut engine = siphash24::HashEngine::with_keys(0, 0);
+    for block in script.blocks.as_slice() {
+        match block {
+            Block::Call(id) => {
+                engine.input(&[1]);
+                engine.input(&id.0.to_le_bytes());
+            }
+            Block::Script(script_buf) => {
+                engine.input(&[0]);
+                engine.input(&(script_buf.len() as u64).to_le_bytes());
+                engine.input(script_buf.as_bytes());
+            }
+        }
+    }
+    ScriptId(siphash24::Hash::from_engine_to_u64(engine))
 }
 
 impl StructuredScript {
@@ -46,7 +85,9 @@ impl StructuredScript {
         StructuredScript {
             size: 0,
             debug_identifier: debug_info.to_string(),
+            doc: None,
             blocks,
+            block_ends: Vec::new(),
             script_map: HashMap::new(),
         }
     }
@@ -55,42 +96,123 @@ impl StructuredScript {
         self.size
     }
 
-    pub fn add_structured_script(&mut self, id: u64, script: StructuredScript) {
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    // Appends a line to the documentation of the script. The space that follows the //! of
+    // a doc comment is removed.
+    pub fn add_doc(mut self, line: &str) -> StructuredScript {
+        let line = line.strip_prefix(' ').unwrap_or(line);
+        match &mut self.doc {
+            Some(doc) => {
+                doc.push('\n');
+                doc.push_str(line);
+            }
+            None => self.doc = Some(line.to_string()),
+        }
+        self
+    }
+
+    pub fn add_structured_script(&mut self, id: ScriptId, script: StructuredScript) {
         self.script_map.entry(id).or_insert(script);
     }
 
-    pub fn get_structured_script(&self, id: &u64) -> &StructuredScript {
+    // Inserts all entries of the script_map of other into the own script_map. Entries that
+    // exist in both maps are merged recursively. This is needed when a Block::Call was
+    // added without registering the called script, e.g. after deserialization.
+    pub fn merge_script_maps(&mut self, other: &StructuredScript) {
+        for (id, script) in &other.script_map {
+            match self.script_map.get_mut(id) {
+                Some(existing) => existing.merge_script_maps(script),
+                None => {
+                    self.script_map.insert(*id, script.clone());
+                }
+            }
+        }
+    }
+
+    // The key under which a caller registers this script in its script_map.
+    pub fn id(&self) -> ScriptId {
+        calculate_hash(self)
+    }
+
+    pub fn get_structured_script(&self, id: &ScriptId) -> &StructuredScript {
         self.script_map
             .get(id)
-            .expect(&format!("script id: {} not found in script_map.", id))
+            .unwrap_or_else(|| panic!("script id: {} not found in script_map.", id))
     }
 
     // Return the debug information of the Opcode at position
     pub fn debug_info(&self, position: usize) -> String {
-        let mut current_pos = 0;
-        for block in &self.blocks {
-            assert!(current_pos <= position, "Target position not found");
-            match block {
-                Block::Call(id) => {
-                    //let called_script = self.get_structured_script(id);
-                    let called_script = self
-                        .script_map
-                        .get(id)
-                        .expect("Missing entry for a called script");
-                    if position >= current_pos && position < current_pos + called_script.len() {
-                        return called_script.debug_info(position - current_pos);
-                    }
-                    current_pos += called_script.len();
-                }
-                Block::Script(script_buf) => {
-                    if position >= current_pos && position < current_pos + script_buf.len() {
-                        return self.debug_identifier.clone();
+        let (index, block_start) = self.find_block(position);
+        match self.blocks.get(index) {
+            Some(Block::Call(id)) => {
+                let called_script = self
+                    .script_map
+                    .get(id)
+                    .expect("Missing entry for a called script");
+                called_script.debug_info(position - block_start)
+            }
+            Some(Block::Script(_)) => self.debug_identifier.clone(),
+            None => panic!("No blocks in the structured script"),
+        }
+    }
+
+    // Index and start position of the block that contains position.
+    fn find_block(&self, position: usize) -> (usize, usize) {
+        if self.block_ends.len() == self.blocks.len() {
+            // The first block that ends after position contains it
+            let index = self.block_ends.partition_point(|&end| end <= position);
+            let block_start = match index {
+                0 => 0,
+                _ => self.block_ends[index - 1],
+            };
+            return (index, block_start);
+        }
+        let mut block_start = 0;
+        for (index, block) in self.blocks.iter().enumerate() {
+            let block_len = match block {
+                Block::Call(id) => self.get_structured_script(id).len(),
+                Block::Script(script_buf) => script_buf.len(),
+            };
+            if position < block_start + block_len {
+                return (index, block_start);
+            }
+            block_start += block_len;
+        }
+        (self.blocks.len(), block_start)
+    }
+
+    // Maps the id of this script and of every script it calls, directly or indirectly, to
+    // the ids of the scripts it calls directly, in the order of their first call.
+    pub fn dependency_graph(&self) -> HashMap<ScriptId, Vec<ScriptId>> {
+        let mut graph = HashMap::new();
+        let mut pending = vec![(calculate_hash(self), self)];
+        while let Some((id, script)) = pending.pop() {
+            if graph.contains_key(&id) {
+                continue;
+            }
+            let mut called_ids: Vec<ScriptId> = Vec::new();
+            for block in script.blocks.as_slice() {
+                if let Block::Call(called_id) = block {
+                    if !called_ids.contains(called_id) {
+                        called_ids.push(*called_id);
+                        pending.push((*called_id, script.get_structured_script(called_id)));
                     }
-                    current_pos += script_buf.len();
                 }
             }
+            graph.insert(id, called_ids);
+        }
+        graph
+    }
+
+    // Returns an iterator over the block tree in depth-first (execution) order. Called
+    // scripts are bracketed by EnterCall and ExitCall items.
+    pub fn depth_first_iter(&self) -> DfsIterator<'_> {
+        DfsIterator {
+            stack: vec![(self, 0, None)],
         }
-        panic!("No blocks in the structured script");
     }
 
     fn get_script_block(&mut self) -> &mut ScriptBuf {
@@ -100,6 +222,7 @@ impl StructuredScript {
         // Create a new Script block if necessary
         if !is_script_block {
             self.blocks.push(Block::new_script());
+            self.block_ends.push(self.size);
         }
 
         if let Some(Block::Script(ref mut script)) = self.blocks.last_mut() {
@@ -109,13 +232,56 @@ impl StructuredScript {
         }
     }
 
+    // Updates the sizes after bytes were appended to the last block.
+    fn grow_last_block(&mut self, added: usize) {
+        self.size += added;
+        *self
+            .block_ends
+            .last_mut()
+            .expect("Growing a script without blocks") = self.size;
+    }
+
     pub fn push_opcode(mut self, data: Opcode) -> StructuredScript {
-        self.size += 1;
         let script = self.get_script_block();
         script.push_opcode(data);
+        self.grow_last_block(1);
+        self
+    }
+
+    fn update(&mut self, f: impl FnOnce(StructuredScript) -> StructuredScript) -> &mut Self {
+        let script = std::mem::replace(self, StructuredScript::new(""));
+        *self = f(script);
         self
     }
 
+    // In-place variants of the push methods, e.g. for pushing in a loop.
+    pub fn mut_push_opcode(&mut self, data: Opcode) -> &mut Self {
+        self.update(|script| script.push_opcode(data))
+    }
+
+    pub fn mut_push_int(&mut self, data: i64) -> &mut Self {
+        self.update(|script| script.push_int(data))
+    }
+
+    pub fn mut_push_slice<T: AsRef<PushBytes>>(&mut self, data: T) -> &mut Self {
+        self.update(|script| script.push_slice(data))
+    }
+
+    pub fn mut_push_env_script(&mut self, data: StructuredScript) -> &mut Self {
+        self.update(|script| script.push_env_script(data))
+    }
+
+    // Pushes an opcode by its byte, e.g. for opcodes of soft fork proposals. Data pushes
+    // have to use push_slice because they are followed by their data.
+    pub fn push_raw_opcode(self, byte: u8) -> StructuredScript {
+        assert!(
+            byte == 0 || byte > OP_PUSHDATA4.to_u8(),
+            "Not an opcode without data: {:#04x}",
+            byte
+        );
+        self.push_opcode(Opcode::from(byte))
+    }
+
     pub fn push_script(mut self, data: ScriptBuf) -> StructuredScript {
         let mut pos = 0;
         for instruction in data.instructions() {
@@ -128,14 +294,77 @@ impl StructuredScript {
         assert_eq!(data.len(), pos, "Pos counting seems to be off");
         self.size += data.len();
         self.blocks.push(Block::Script(data));
+        self.block_ends.push(self.size);
         self
     }
 
+    // Removes everything after the first len bytes. len has to be a block boundary or lie
+    // on an instruction boundary inside a script block.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        assert!(
+            len <= self.size,
+            "Can not truncate the script to a larger size"
+        );
+        let mut current_pos = 0;
+        let mut keep_blocks = 0;
+        for block in self.blocks.iter_mut() {
+            if current_pos == len {
+                break;
+            }
+            let block_len = match block {
+                Block::Call(id) => self
+                    .script_map
+                    .get(id)
+                    .expect("Missing entry for a called script")
+                    .len(),
+                Block::Script(script_buf) => script_buf.len(),
+            };
+            if current_pos + block_len > len {
+                match block {
+                    Block::Call(_) => panic!("Can not truncate the script inside a called script"),
+                    Block::Script(script_buf) => {
+                        let mut bytes = std::mem::take(script_buf).into_bytes();
+                        bytes.truncate(len - current_pos);
+                        *script_buf = ScriptBuf::from_bytes(bytes);
+                    }
+                }
+                current_pos = len;
+            } else {
+                current_pos += block_len;
+            }
+            keep_blocks += 1;
+        }
+        self.blocks.truncate(keep_blocks);
+        self.block_ends.truncate(keep_blocks);
+        if let Some(end) = self.block_ends.last_mut() {
+            *end = len;
+        }
+        self.size = len;
+
+        // Drop the called scripts which are not referenced anymore.
+        let called_ids: Vec<ScriptId> = self
+            .blocks
+            .iter()
+            .filter_map(|block| match block {
+                Block::Call(id) => Some(*id),
+                Block::Script(_) => None,
+            })
+            .collect();
+        self.script_map.retain(|id, _| called_ids.contains(id));
+    }
+
     pub fn push_env_script(mut self, mut data: StructuredScript) -> StructuredScript {
-        if data.len() == 0 {
+        if data.is_empty() {
             return self;
         }
-        if self.len() == 0 {
+        if self.is_empty() {
+            // Keep the doc of the caller in front of the doc of the inlined script
+            if let Some(doc) = self.doc {
+                data.doc = Some(match data.doc {
+                    Some(data_doc) => format!("{}\n{}", doc, data_doc),
+                    None => doc,
+                });
+            }
             return data;
         }
 
@@ -143,6 +372,7 @@ impl StructuredScript {
         self.size += data.len();
         let id = calculate_hash(&data);
         self.blocks.push(Block::Call(id));
+        self.block_ends.push(self.size);
         // Register script in the script map
         self.add_structured_script(id, data);
         self
@@ -150,7 +380,7 @@ impl StructuredScript {
 
     // Compiles the builder to bytes using a cache that stores all called_script starting
     // positions in script to copy them from script instead of recompiling.
-    fn compile_to_bytes(&self, script: &mut Vec<u8>, cache: &mut HashMap<u64, usize>) {
+    fn compile_to_bytes(&self, script: &mut Vec<u8>, cache: &mut HashMap<ScriptId, usize>) {
         for block in self.blocks.as_slice() {
             match block {
                 Block::Call(id) => {
@@ -234,6 +464,69 @@ impl StructuredScript {
         script_buf
     }
 
+    // Builds a taproot tree with this script as its only leaf.
+    pub fn as_taproot_spend_info(self, internal_key: XOnlyPublicKey) -> TaprootSpendInfo {
+        TaprootBuilder::new()
+            .add_leaf(0, self.compile())
+            .expect("A single leaf at depth 0 is a valid tree")
+            .finalize(&Secp256k1::verification_only(), internal_key)
+            .expect("A single leaf tree is always complete")
+    }
+
+    pub fn p2tr_address(&self, network: Network, internal_key: XOnlyPublicKey) -> Address {
+        let spend_info = self.clone().as_taproot_spend_info(internal_key);
+        Address::p2tr_tweaked(spend_info.output_key(), network)
+    }
+
+    pub fn into_p2wsh_witness_script(self) -> P2WSHScript {
+        P2WSHScript { inner: self }
+    }
+
+    // True if both scripts compile to the same bytes, no matter how they are structured.
+    // Scripts of different length are not compiled.
+    pub fn compiled_eq(&self, other: &StructuredScript) -> bool {
+        self.len() == other.len() && self.clone().compile() == other.clone().compile()
+    }
+
+    // SHA256 of the compiled script. In contrast to the ids in script_map it only depends
+    // on the compiled bytes and not on how the script is structured.
+    pub fn content_hash(&self) -> [u8; 32] {
+        <sha256::Hash as BitcoinHash>::hash(self.clone().compile().as_bytes()).to_byte_array()
+    }
+
+    // SHA256 of the block tree, computed without compiling the script. A script block is
+    // hashed as the tag 0x00 followed by its length as u64 (little endian) and its bytes.
+    // A call is hashed as the tag 0x01 followed by the structure hash of the called script.
+    pub fn structure_hash(&self) -> [u8; 32] {
+        self.structure_hash_cached(&mut HashMap::new())
+    }
+
+    fn structure_hash_cached(&self, cache: &mut HashMap<ScriptId, [u8; 32]>) -> [u8; 32] {
+        let mut engine = sha256::Hash::engine();
+        for block in &self.blocks {
+            match block {
+                Block::Call(id) => {
+                    let called_hash = match cache.get(id) {
+                        Some(hash) => *hash,
+                        None => {
+                            let hash = self.get_structured_script(id).structure_hash_cached(cache);
+                            cache.insert(*id, hash);
+                            hash
+                        }
+                    };
+                    engine.input(&[1]);
+                    engine.input(&called_hash);
+                }
+                Block::Script(script_buf) => {
+                    engine.input(&[0]);
+                    engine.input(&(script_buf.len() as u64).to_le_bytes());
+                    engine.input(script_buf.as_bytes());
+                }
+            }
+        }
+        sha256::Hash::from_engine(engine).to_byte_array()
+    }
+
     pub fn push_int(self, data: i64) -> StructuredScript {
         // We can special-case -1, 1-16
         if data == -1 || (1..=16).contains(&data) {
@@ -259,7 +552,8 @@ impl StructuredScript {
         let script = self.get_script_block();
         let old_size = script.len();
         script.push_slice(data);
-        self.size += script.len() - old_size;
+        let added = script.len() - old_size;
+        self.grow_last_block(added);
         self
     }
 
@@ -275,9 +569,546 @@ impl StructuredScript {
         self.push_slice(x_only_key.serialize())
     }
 
+    // Pushes the DER encoded signature followed by its sighash type byte.
+    pub fn push_der_sig(self, sig: &::bitcoin::ecdsa::Signature) -> StructuredScript {
+        self.push_slice(sig.serialize())
+    }
+
+    // Pushes the 64 byte Schnorr signature, followed by the sighash type byte unless it is
+    // SIGHASH_DEFAULT.
+    pub fn push_schnorr_sig(self, sig: &::bitcoin::taproot::Signature) -> StructuredScript {
+        self.push_slice(PushBytesBuf::try_from(sig.to_vec()).unwrap())
+    }
+
     pub fn push_expression<T: Pushable>(self, expression: T) -> StructuredScript {
         expression.bitcoin_script_push(self)
     }
+
+    // Fails the script unless lo <= x <= hi for the number x on top of the stack, which is
+    // left on the stack.
+    pub fn push_range_check(self, lo: i64, hi: i64) -> StructuredScript {
+        assert!(lo <= hi, "Empty range: {} > {}", lo, hi);
+        self.push_opcode(OP_DUP)
+            .push_int(lo)
+            .push_opcode(OP_GREATERTHANOREQUAL)
+            .push_opcode(OP_VERIFY)
+            .push_opcode(OP_DUP)
+            .push_int(hi)
+            .push_opcode(OP_LESSTHANOREQUAL)
+            .push_opcode(OP_VERIFY)
+    }
+
+    // Like push_range_check, but the number is consumed.
+    pub fn push_range_check_consuming(self, lo: i64, hi: i64) -> StructuredScript {
+        assert!(lo <= hi, "Empty range: {} > {}", lo, hi);
+        self.push_opcode(OP_DUP)
+            .push_int(lo)
+            .push_opcode(OP_GREATERTHANOREQUAL)
+            .push_opcode(OP_VERIFY)
+            .push_int(hi)
+            .push_opcode(OP_LESSTHANOREQUAL)
+            .push_opcode(OP_VERIFY)
+    }
+
+    // Returns a flat copy of the script with a `<id> OP_DROP` marker in front of every
+    // interval-th instruction. The marker with identifier i was inserted at the byte
+    // position returned at index i of the vector. The markers do not change the stack.
+    pub fn mark_coverage_points(&self, interval: usize) -> (StructuredScript, Vec<usize>) {
+        assert!(interval > 0, "Coverage interval has to be positive");
+        let compiled = self.clone().compile();
+        let mut marked = StructuredScript::new(&self.debug_identifier);
+        let mut positions = Vec::new();
+        for (index, result) in compiled.instruction_indices().enumerate() {
+            let (position, instruction) = result.expect("Compiled script is valid");
+            if index % interval == 0 {
+                marked = marked.push_int(positions.len() as i64).push_opcode(OP_DROP);
+                positions.push(position);
+            }
+            marked = match instruction {
+                Instruction::Op(opcode) => marked.push_opcode(opcode),
+                Instruction::PushBytes(push_bytes) => marked.push_slice(push_bytes),
+            };
+        }
+        (marked, positions)
+    }
+
+    // Upper bound for the number of execution paths: every OP_IF and OP_NOTIF, with or
+    // without OP_ELSE, doubles the number of paths.
+    pub fn execution_path_count(&self) -> usize {
+        let mut branches = 0;
+        for item in self.depth_first_iter() {
+            if let DfsItem::ScriptBuf { buf, .. } = item {
+                branches += buf
+                    .instructions()
+                    .flatten()
+                    .filter(|instruction| matches!(instruction, Instruction::Op(OP_IF | OP_NOTIF)))
+                    .count();
+            }
+        }
+        u32::try_from(branches)
+            .ok()
+            .and_then(|branches| 2usize.checked_pow(branches))
+            .unwrap_or(usize::MAX)
+    }
+
+    // Maximum nesting depth of OP_IF/OP_NOTIF blocks. Blocks opened in a caller count for
+    // the IF blocks of the called scripts.
+    pub fn max_if_depth(&self) -> usize {
+        let mut depth: usize = 0;
+        let mut max_depth = 0;
+        for item in self.depth_first_iter() {
+            if let DfsItem::ScriptBuf { buf, .. } = item {
+                for instruction in buf.instructions().flatten() {
+                    match instruction {
+                        Instruction::Op(OP_IF | OP_NOTIF) => {
+                            depth += 1;
+                            max_depth = max_depth.max(depth);
+                        }
+                        Instruction::Op(OP_ENDIF) => depth = depth.saturating_sub(1),
+                        _ => (),
+                    }
+                }
+            }
+        }
+        max_depth
+    }
+
+    // Returns every opcode used by the script, including the opcodes of data pushes, in
+    // the order of their byte values. Opcode does not implement Hash, so this is a Vec.
+    pub fn unique_opcodes(&self) -> Vec<Opcode> {
+        let mut used = [false; 256];
+        for item in self.depth_first_iter() {
+            if let DfsItem::ScriptBuf { buf, .. } = item {
+                for (position, _) in buf.instruction_indices().flatten() {
+                    used[buf.as_bytes()[position] as usize] = true;
+                }
+            }
+        }
+        (0..=u8::MAX)
+            .filter(|&byte| used[byte as usize])
+            .map(Opcode::from)
+            .collect()
+    }
+
+    pub fn count_unique_opcodes(&self) -> usize {
+        self.unique_opcodes().len()
+    }
+
+    pub fn to_compact_repr(&self) -> CompactScript {
+        let mut compact = CompactScript {
+            scripts: Vec::new(),
+            nodes: Vec::new(),
+        };
+        self.add_compact_node(&mut compact, &mut HashMap::new(), &mut HashMap::new());
+        compact
+    }
+
+    // Appends the node of this script and all scripts it calls which are not yet in compact.
+    // Called scripts are identified by their id and debug identifier because the same script
+    // can be registered with different debug identifiers by different callers.
+    fn add_compact_node<'a>(
+        &'a self,
+        compact: &mut CompactScript,
+        script_indices: &mut HashMap<&'a ScriptBuf, u32>,
+        node_indices: &mut HashMap<(ScriptId, &'a str), u32>,
+    ) -> u32 {
+        let mut blocks = Vec::with_capacity(self.blocks.len());
+        for block in self.blocks.as_slice() {
+            blocks.push(match block {
+                Block::Call(id) => {
+                    let called_script = self.get_structured_script(id);
+                    let key = (*id, called_script.debug_identifier.as_str());
+                    let node = match node_indices.get(&key) {
+                        Some(node) => *node,
+                        None => {
+                            let node = called_script.add_compact_node(
+                                compact,
+                                script_indices,
+                                node_indices,
+                            );
+                            node_indices.insert(key, node);
+                            node
+                        }
+                    };
+                    CompactBlock::Call(node)
+                }
+                Block::Script(script_buf) => {
+                    CompactBlock::Script(*script_indices.entry(script_buf).or_insert_with(|| {
+                        compact.scripts.push(script_buf.clone());
+                        (compact.scripts.len() - 1) as u32
+                    }))
+                }
+            });
+        }
+        compact.nodes.push(CompactNode {
+            debug_identifier: self.debug_identifier.clone(),
+            doc: self.doc.clone(),
+            blocks,
+        });
+        (compact.nodes.len() - 1) as u32
+    }
+
+    // Returns None if the compact script references missing entries, like from_bytes.
+    pub fn from_compact_repr(compact: CompactScript) -> Option<StructuredScript> {
+        if !compact.is_valid() {
+            return None;
+        }
+        let mut built = vec![None; compact.nodes.len()];
+        let root = compact.nodes.len() - 1;
+        Some(StructuredScript::from_compact_node(
+            &compact,
+            root as u32,
+            &mut built,
+        ))
+    }
+
+    fn from_compact_node(
+        compact: &CompactScript,
+        index: u32,
+        built: &mut Vec<Option<StructuredScript>>,
+    ) -> StructuredScript {
+        if let Some(script) = &built[index as usize] {
+            return script.clone();
+        }
+        let node = &compact.nodes[index as usize];
+        let mut script = StructuredScript::new(&node.debug_identifier);
+        script.doc = node.doc.clone();
+        for block in &node.blocks {
+            match block {
+                CompactBlock::Call(called) => {
+                    let called_script =
+                        StructuredScript::from_compact_node(compact, *called, built);
+                    let id = calculate_hash(&called_script);
+                    script.size += called_script.len();
+                    script.blocks.push(Block::Call(id));
+                    script.block_ends.push(script.size);
+                    script.add_structured_script(id, called_script);
+                }
+                CompactBlock::Script(script_index) => {
+                    let script_buf = &compact.scripts[*script_index as usize];
+                    script.size += script_buf.len();
+                    script.blocks.push(Block::Script(script_buf.clone()));
+                    script.block_ends.push(script.size);
+                }
+            }
+        }
+        built[index as usize] = Some(script.clone());
+        script
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompactBlock {
+    // Index into CompactScript::nodes
+    Call(u32),
+    // Index into CompactScript::scripts
+    Script(u32),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompactNode {
+    pub debug_identifier: String,
+    pub doc: Option<String>,
+    pub blocks: Vec<CompactBlock>,
+}
+
+// Flat representation of a StructuredScript in which every distinct script block and every
+// distinct called script is stored only once. The last node is the root script and called
+// nodes always come before their callers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompactScript {
+    pub scripts: Vec<ScriptBuf>,
+    pub nodes: Vec<CompactNode>,
+}
+
+impl CompactScript {
+    // True if there is a root node and every block references an existing entry. Calls have
+    // to point to earlier nodes to rule out cycles.
+    pub fn is_valid(&self) -> bool {
+        !self.nodes.is_empty()
+            && self.nodes.iter().enumerate().all(|(node_index, node)| {
+                node.blocks.iter().all(|block| match block {
+                    CompactBlock::Call(index) => (*index as usize) < node_index,
+                    CompactBlock::Script(index) => (*index as usize) < self.scripts.len(),
+                })
+            })
+    }
+
+    // Serializes the compact script with little endian u32 counts, lengths and indices.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let write_u32 = |bytes: &mut Vec<u8>, n: usize| {
+            let n = u32::try_from(n).expect("Compact script entries are limited to u32");
+            bytes.extend_from_slice(&n.to_le_bytes())
+        };
+        write_u32(&mut bytes, self.scripts.len());
+        for script in &self.scripts {
+            write_u32(&mut bytes, script.len());
+            bytes.extend_from_slice(script.as_bytes());
+        }
+        write_u32(&mut bytes, self.nodes.len());
+        for node in &self.nodes {
+            write_u32(&mut bytes, node.debug_identifier.len());
+            bytes.extend_from_slice(node.debug_identifier.as_bytes());
+            match &node.doc {
+                Some(doc) => {
+                    bytes.push(1);
+                    write_u32(&mut bytes, doc.len());
+                    bytes.extend_from_slice(doc.as_bytes());
+                }
+                None => bytes.push(0),
+            }
+            write_u32(&mut bytes, node.blocks.len());
+            for block in &node.blocks {
+                let (tag, index) = match block {
+                    CompactBlock::Call(index) => (0, index),
+                    CompactBlock::Script(index) => (1, index),
+                };
+                bytes.push(tag);
+                write_u32(&mut bytes, *index as usize);
+            }
+        }
+        bytes
+    }
+
+    // Returns None if the bytes are malformed or reference missing entries.
+    pub fn from_bytes(mut bytes: &[u8]) -> Option<CompactScript> {
+        fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+            if bytes.len() < len {
+                return None;
+            }
+            let (taken, rest) = bytes.split_at(len);
+            *bytes = rest;
+            Some(taken)
+        }
+        fn read_u32(bytes: &mut &[u8]) -> Option<u32> {
+            Some(u32::from_le_bytes(take(bytes, 4)?.try_into().ok()?))
+        }
+
+        let script_count = read_u32(&mut bytes)?;
+        let mut scripts = Vec::new();
+        for _ in 0..script_count {
+            let len = read_u32(&mut bytes)? as usize;
+            scripts.push(ScriptBuf::from_bytes(take(&mut bytes, len)?.to_vec()));
+        }
+        let node_count = read_u32(&mut bytes)?;
+        let mut nodes = Vec::new();
+        for _ in 0..node_count {
+            let len = read_u32(&mut bytes)? as usize;
+            let debug_identifier = String::from_utf8(take(&mut bytes, len)?.to_vec()).ok()?;
+            let doc = match take(&mut bytes, 1)?[0] {
+                0 => None,
+                1 => {
+                    let len = read_u32(&mut bytes)? as usize;
+                    Some(String::from_utf8(take(&mut bytes, len)?.to_vec()).ok()?)
+                }
+                _ => return None,
+            };
+            let block_count = read_u32(&mut bytes)?;
+            let mut blocks = Vec::new();
+            for _ in 0..block_count {
+                let tag = take(&mut bytes, 1)?[0];
+                let index = read_u32(&mut bytes)?;
+                blocks.push(match tag {
+                    0 => CompactBlock::Call(index),
+                    1 => CompactBlock::Script(index),
+                    _ => return None,
+                });
+            }
+            nodes.push(CompactNode {
+                debug_identifier,
+                doc,
+                blocks,
+            });
+        }
+        if !bytes.is_empty() {
+            return None;
+        }
+        Some(CompactScript { scripts, nodes }).filter(CompactScript::is_valid)
+    }
+}
+
+// A witness script of a P2WSH output. The funding output commits to its SHA256 hash.
+#[derive(Clone, Debug)]
+pub struct P2WSHScript {
+    inner: StructuredScript,
+}
+
+impl P2WSHScript {
+    pub fn into_inner(self) -> StructuredScript {
+        self.inner
+    }
+
+    // The script as it is pushed as the last witness element.
+    pub fn witness_script_bytes(&self) -> Vec<u8> {
+        self.inner.clone().compile().into_bytes()
+    }
+
+    // The scriptPubKey of the funding output: OP_0 <32-byte hash>.
+    pub fn locking_script(&self) -> StructuredScript {
+        let script_hash = self.inner.clone().compile().wscript_hash();
+        StructuredScript::new("p2wsh")
+            .push_opcode(OP_0)
+            .push_slice(script_hash.as_byte_array())
+    }
+
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2wsh(&self.inner.clone().compile(), network)
+    }
+}
+
+// Repeats the script rhs times. The first repetition is inlined like every first push of
+// push_env_script, the others are calls of the same subscript, which is only compiled once.
+impl Mul<usize> for StructuredScript {
+    type Output = StructuredScript;
+
+    fn mul(self, rhs: usize) -> StructuredScript {
+        match rhs {
+            0 => StructuredScript::new(""),
+            1 => self,
+            _ => (0..rhs).fold(
+                StructuredScript::new(&self.debug_identifier),
+                |script, _| script.push_env_script(self.clone()),
+            ),
+        }
+    }
+}
+
+impl MulAssign<usize> for StructuredScript {
+    fn mul_assign(&mut self, rhs: usize) {
+        let script = std::mem::replace(self, StructuredScript::new(""));
+        *self = script * rhs;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DfsItem<'a> {
+    EnterCall {
+        id: ScriptId,
+        debug_id: &'a str,
+        depth: usize,
+    },
+    ScriptBuf {
+        buf: &'a ScriptBuf,
+        debug_id: &'a str,
+    },
+    ExitCall {
+        id: ScriptId,
+        depth: usize,
+    },
+}
+
+// Walks the block tree with an explicit stack instead of recursion so that deeply nested
+// scripts can not overflow the call stack.
+pub struct DfsIterator<'a> {
+    // (script, index of the next block, id under which the script was called)
+    stack: Vec<(&'a StructuredScript, usize, Option<ScriptId>)>,
+}
+
+impl<'a> Iterator for DfsIterator<'a> {
+    type Item = DfsItem<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let depth = self.stack.len().checked_sub(1)?;
+            let (script, index, _) = self.stack.last_mut()?;
+            let script: &'a StructuredScript = script;
+            match script.blocks.get(*index) {
+                Some(Block::Call(id)) => {
+                    *index += 1;
+                    let called_script = script.get_structured_script(id);
+                    self.stack.push((called_script, 0, Some(*id)));
+                    return Some(DfsItem::EnterCall {
+                        id: *id,
+                        debug_id: &called_script.debug_identifier,
+                        depth: depth + 1,
+                    });
+                }
+                Some(Block::Script(buf)) => {
+                    *index += 1;
+                    return Some(DfsItem::ScriptBuf {
+                        buf,
+                        debug_id: &script.debug_identifier,
+                    });
+                }
+                None => {
+                    if let Some((_, _, Some(id))) = self.stack.pop() {
+                        return Some(DfsItem::ExitCall { id, depth });
+                    }
+                }
+            }
+        }
+    }
+}
+
+// A builder that modifies the script in place. It can save checkpoints of the current
+// size and roll back to them, which allows to try out a construction and discard it.
+pub struct ScriptBuilder {
+    script: StructuredScript,
+}
+
+impl ScriptBuilder {
+    pub fn new(debug_info: &str) -> Self {
+        ScriptBuilder {
+            script: StructuredScript::new(debug_info),
+        }
+    }
+
+    fn update(&mut self, f: impl FnOnce(StructuredScript) -> StructuredScript) -> &mut Self {
+        self.script.update(f);
+        self
+    }
+
+    pub fn push_opcode(&mut self, data: Opcode) -> &mut Self {
+        self.update(|script| script.push_opcode(data))
+    }
+
+    pub fn push_int(&mut self, data: i64) -> &mut Self {
+        self.update(|script| script.push_int(data))
+    }
+
+    pub fn push_slice<T: AsRef<PushBytes>>(&mut self, data: T) -> &mut Self {
+        self.update(|script| script.push_slice(data))
+    }
+
+    pub fn push_script(&mut self, data: ScriptBuf) -> &mut Self {
+        self.update(|script| script.push_script(data))
+    }
+
+    pub fn push_env_script(&mut self, data: StructuredScript) -> &mut Self {
+        self.update(|script| script.push_env_script(data))
+    }
+
+    pub fn push_expression<T: Pushable>(&mut self, expression: T) -> &mut Self {
+        self.update(|script| script.push_expression(expression))
+    }
+
+    pub fn len(&self) -> usize {
+        self.script.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.script.is_empty()
+    }
+
+    // Returns the current size which can later be passed to rollback_to.
+    pub fn checkpoint(&self) -> usize {
+        self.script.len()
+    }
+
+    // Removes everything that was pushed after the checkpoint was taken.
+    pub fn rollback_to(&mut self, checkpoint: usize) -> &mut Self {
+        self.script.truncate(checkpoint);
+        self
+    }
+
+    pub fn script(&self) -> &StructuredScript {
+        &self.script
+    }
+
+    pub fn finish(self) -> StructuredScript {
+        self.script
+    }
 }
 
 // We split up the bitcoin_script_push function to allow pushing a single u8 value as
@@ -285,6 +1116,9 @@ impl StructuredScript {
 // not a u8. Otherwise the Vec<u8> and Vec<T: Pushable> definitions conflict.
 trait NotU8Pushable {
     fn bitcoin_script_push(self, builder: StructuredScript) -> StructuredScript;
+    fn pushed_identifier(&self) -> Option<String> {
+        None
+    }
 }
 impl NotU8Pushable for i64 {
     fn bitcoin_script_push(self, builder: StructuredScript) -> StructuredScript {
@@ -344,6 +1178,9 @@ impl NotU8Pushable for StructuredScript {
     fn bitcoin_script_push(self, builder: StructuredScript) -> StructuredScript {
         builder.push_env_script(self)
     }
+    fn pushed_identifier(&self) -> Option<String> {
+        Some(self.debug_identifier.clone())
+    }
 }
 impl<T: NotU8Pushable> NotU8Pushable for Vec<T> {
     fn bitcoin_script_push(self, mut builder: StructuredScript) -> StructuredScript {
@@ -352,14 +1189,28 @@ impl<T: NotU8Pushable> NotU8Pushable for Vec<T> {
         }
         builder
     }
+    fn pushed_identifier(&self) -> Option<String> {
+        let identifiers: Vec<String> = self
+            .iter()
+            .filter_map(NotU8Pushable::pushed_identifier)
+            .collect();
+        (!identifiers.is_empty()).then(|| identifiers.join(", "))
+    }
 }
 pub trait Pushable {
     fn bitcoin_script_push(self, builder: StructuredScript) -> StructuredScript;
+    // Debug identifier of the pushed scripts, None if only data is pushed.
+    fn pushed_identifier(&self) -> Option<String> {
+        None
+    }
 }
 impl<T: NotU8Pushable> Pushable for T {
     fn bitcoin_script_push(self, builder: StructuredScript) -> StructuredScript {
         NotU8Pushable::bitcoin_script_push(self, builder)
     }
+    fn pushed_identifier(&self) -> Option<String> {
+        NotU8Pushable::pushed_identifier(self)
+    }
 }
 
 impl Pushable for u8 {
@@ -367,3 +1218,56 @@ impl Pushable for u8 {
         builder.push_int(self as i64)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::opcodes::all::{OP_ADD, OP_DUP};
+
+    #[test]
+    fn merge_script_maps() {
+        let inner = StructuredScript::new("inner").push_opcode(OP_ADD);
+        let called = StructuredScript::new("called")
+            .push_opcode(OP_DUP)
+            .push_env_script(inner.clone());
+        let sibling = StructuredScript::new("sibling")
+            .push_opcode(OP_DUP)
+            .push_env_script(called.clone());
+        let id = calculate_hash(&called);
+
+        // Inject the call without registering the called script.
+        let mut script = StructuredScript::new("script").push_opcode(OP_DUP);
+        script.blocks.push(Block::Call(id));
+        script.size += called.len();
+        script.block_ends.push(script.size);
+        assert!(script.script_map.is_empty());
+
+        script.merge_script_maps(&sibling);
+        assert_eq!(script.get_structured_script(&id).len(), called.len());
+        assert_eq!(
+            script.compile().to_bytes(),
+            vec![OP_DUP.to_u8(), OP_DUP.to_u8(), OP_ADD.to_u8()]
+        );
+    }
+
+    #[test]
+    fn merge_script_maps_recursively() {
+        let inner = StructuredScript::new("inner").push_opcode(OP_ADD);
+        let called = StructuredScript::new("called").push_opcode(OP_DUP);
+        let id = calculate_hash(&called);
+        let inner_id = calculate_hash(&inner);
+
+        let mut script = StructuredScript::new("script")
+            .push_opcode(OP_DUP)
+            .push_env_script(called.clone());
+        // The same script id, but its own script_map knows about the inner script.
+        let mut other_called = called.clone();
+        other_called.add_structured_script(inner_id, inner);
+        let mut other = StructuredScript::new("other");
+        other.add_structured_script(id, other_called);
+
+        script.merge_script_maps(&other);
+        let merged = script.get_structured_script(&id);
+        assert_eq!(merged.get_structured_script(&inner_id).len(), 1);
+    }
+}
@@ -0,0 +1,143 @@
+use crate::builder::StructuredScript;
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::opcodes::all::*;
+use std::collections::HashMap;
+
+// Assigns an execution cost to every instruction of a script.
+pub trait CostModel {
+    // input_size is the size of the top stack item if it was pushed or hashed right before
+    // the instruction, otherwise it is not known statically.
+    fn cost(&self, instruction: Instruction, input_size: Option<usize>) -> u64;
+}
+
+// Hashes cost hash_block_cost per started 64 byte block of their input and pushes cost
+// one per started 32 bytes. Every other opcode costs opcode_cost, signature checks
+// checksig_cost.
+#[derive(Clone, Debug)]
+pub struct DefaultCostModel {
+    pub opcode_cost: u64,
+    pub checksig_cost: u64,
+    pub hash_block_cost: u64,
+    // Assumed input size of a hash if it is not known statically.
+    pub default_hash_input_size: usize,
+}
+
+impl Default for DefaultCostModel {
+    fn default() -> Self {
+        DefaultCostModel {
+            opcode_cost: 1,
+            checksig_cost: 50,
+            hash_block_cost: 10,
+            default_hash_input_size: 64,
+        }
+    }
+}
+
+impl CostModel for DefaultCostModel {
+    fn cost(&self, instruction: Instruction, input_size: Option<usize>) -> u64 {
+        match instruction {
+            Instruction::PushBytes(push_bytes) => push_bytes.len().div_ceil(32).max(1) as u64,
+            Instruction::Op(OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256) => {
+                let size = input_size.unwrap_or(self.default_hash_input_size);
+                // Padding adds at least 9 bytes to the input
+                (size + 9).div_ceil(64) as u64 * self.hash_block_cost
+            }
+            Instruction::Op(OP_CHECKSIG | OP_CHECKSIGVERIFY | OP_CHECKSIGADD) => self.checksig_cost,
+            Instruction::Op(_) => self.opcode_cost,
+        }
+    }
+}
+
+// Cost of the most expensive execution path and how much every debug identifier
+// contributes to it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CostEstimate {
+    pub total: u64,
+    pub by_identifier: HashMap<String, u64>,
+}
+
+impl CostEstimate {
+    fn add(&mut self, identifier: String, cost: u64) {
+        self.total += cost;
+        *self.by_identifier.entry(identifier).or_insert(0) += cost;
+    }
+
+    fn extend(&mut self, other: CostEstimate) {
+        for (identifier, cost) in other.by_identifier {
+            self.add(identifier, cost);
+        }
+    }
+
+    // The identifiers ordered by their cost, the most expensive first.
+    pub fn ranking(&self) -> Vec<(&str, u64)> {
+        let mut ranking: Vec<(&str, u64)> = self
+            .by_identifier
+            .iter()
+            .map(|(identifier, cost)| (identifier.as_str(), *cost))
+            .collect();
+        ranking.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        ranking
+    }
+}
+
+impl StructuredScript {
+    // Estimates the cost of the most expensive path through the script. Like sig_budget
+    // it takes the more expensive branch of every IF block. The flow control opcodes are
+    // always executed, so they count for every path.
+    pub fn estimate_cost(&self, model: &dyn CostModel) -> CostEstimate {
+        let script = self.clone().compile();
+        let mut cost = CostEstimate::default();
+        // Cost before the IF block and of its most expensive finished branch.
+        let mut branches: Vec<(CostEstimate, CostEstimate)> = Vec::new();
+        let mut input_size = None;
+        for (position, instruction) in script.instruction_indices().flatten() {
+            let instruction_cost = model.cost(instruction, input_size);
+            let identifier = self.debug_info(position);
+            match instruction {
+                Instruction::Op(OP_IF | OP_NOTIF) => {
+                    cost.add(identifier, instruction_cost);
+                    branches.push((std::mem::take(&mut cost), CostEstimate::default()));
+                }
+                Instruction::Op(OP_ELSE) if !branches.is_empty() => {
+                    let (before, max_branch) = branches.last_mut().unwrap();
+                    before.add(identifier, instruction_cost);
+                    let branch = std::mem::take(&mut cost);
+                    if branch.total > max_branch.total {
+                        *max_branch = branch;
+                    }
+                }
+                Instruction::Op(OP_ENDIF) if !branches.is_empty() => {
+                    let (before, max_branch) = branches.pop().unwrap();
+                    cost = close_branch(before, max_branch, cost);
+                    cost.add(identifier, instruction_cost);
+                }
+                _ => cost.add(identifier, instruction_cost),
+            }
+            input_size = match instruction {
+                Instruction::PushBytes(push_bytes) => Some(push_bytes.len()),
+                // Hashes have a fixed output size, so chained hashes know their input size
+                Instruction::Op(OP_SHA256 | OP_HASH256) => Some(32),
+                Instruction::Op(OP_RIPEMD160 | OP_SHA1 | OP_HASH160) => Some(20),
+                Instruction::Op(_) => None,
+            };
+        }
+        // Close unterminated IF blocks
+        while let Some((before, max_branch)) = branches.pop() {
+            cost = close_branch(before, max_branch, cost);
+        }
+        cost
+    }
+}
+
+fn close_branch(
+    mut before: CostEstimate,
+    max_branch: CostEstimate,
+    branch: CostEstimate,
+) -> CostEstimate {
+    before.extend(if branch.total > max_branch.total {
+        branch
+    } else {
+        max_branch
+    });
+    before
+}
@@ -0,0 +1,34 @@
+use crate::builder::StructuredScript;
+
+const BYTES_PER_LINE: usize = 16;
+
+// Formats the bytes as comma separated hex literals with BYTES_PER_LINE bytes per line.
+fn hex_lines(bytes: &[u8]) -> String {
+    let mut lines = String::new();
+    for chunk in bytes.chunks(BYTES_PER_LINE) {
+        let line: Vec<String> = chunk.iter().map(|byte| format!("0x{:02x}", byte)).collect();
+        lines.push_str("    ");
+        lines.push_str(&line.join(", "));
+        lines.push_str(",\n");
+    }
+    lines
+}
+
+impl StructuredScript {
+    // Compiles the script into a C array definition. Note that an empty script results in
+    // an empty initializer list which is only valid since C23.
+    pub fn to_c_array(&self, var_name: &str) -> String {
+        let bytes = self.clone().compile().into_bytes();
+        format!(
+            "static const uint8_t {}[] = {{\n{}}};\n",
+            var_name,
+            hex_lines(&bytes)
+        )
+    }
+
+    // Compiles the script into a Rust byte slice constant.
+    pub fn to_rust_array(&self, var_name: &str) -> String {
+        let bytes = self.clone().compile().into_bytes();
+        format!("const {}: &[u8] = &[\n{}];\n", var_name, hex_lines(&bytes))
+    }
+}
@@ -0,0 +1,155 @@
+use crate::builder::StructuredScript;
+use arbitrary::{Arbitrary, Result, Unstructured};
+use bitcoin::blockdata::opcodes::Opcode;
+use bitcoin::opcodes::all::{OP_ELSE, OP_ENDIF, OP_IF, OP_NOTIF, OP_PUSHNUM_NEG1};
+
+// Largest data push the generator emits. Keeps generated scripts small enough for fast
+// property tests while still exercising OP_PUSHDATA1.
+const MAX_GENERATED_PUSH: usize = 80;
+
+#[derive(Clone, Debug)]
+pub struct ArbitraryConfig {
+    // Upper bound for the length in bytes of the generated script.
+    pub max_size: usize,
+    // Maximum number of nested calls (and IF blocks).
+    pub max_nesting: usize,
+    // Probability that a call reuses an already generated subscript instead of a fresh one.
+    pub call_reuse_probability: f64,
+    // Whether balanced OP_IF/OP_NOTIF ... OP_ELSE ... OP_ENDIF blocks are generated.
+    pub flow_control: bool,
+}
+
+impl Default for ArbitraryConfig {
+    fn default() -> Self {
+        ArbitraryConfig {
+            max_size: 4096,
+            max_nesting: 4,
+            call_reuse_probability: 0.5,
+            flow_control: true,
+        }
+    }
+}
+
+struct Generator<'c> {
+    config: &'c ArbitraryConfig,
+    remaining: usize,
+    // Previously generated subscripts that can be called again.
+    pool: Vec<StructuredScript>,
+}
+
+impl Generator<'_> {
+    fn chance(&self, u: &mut Unstructured, probability: f64) -> Result<bool> {
+        let threshold = (probability.clamp(0.0, 1.0) * 256.0) as u16;
+        Ok((u8::arbitrary(u)? as u16) < threshold)
+    }
+
+    // Any opcode that is neither a data push nor flow control.
+    fn opcode(&self, u: &mut Unstructured) -> Result<Opcode> {
+        loop {
+            let byte = u.int_in_range(OP_PUSHNUM_NEG1.to_u8()..=u8::MAX)?;
+            if !(OP_IF.to_u8()..=OP_ENDIF.to_u8()).contains(&byte) {
+                return Ok(Opcode::from(byte));
+            }
+        }
+    }
+
+    fn script(&mut self, u: &mut Unstructured, depth: usize) -> Result<StructuredScript> {
+        let mut script = StructuredScript::new(&format!("arbitrary_{}", depth));
+        let elements = u.int_in_range(0..=8)?;
+        for _ in 0..elements {
+            if self.remaining == 0 || u.is_empty() {
+                break;
+            }
+            script = match u.int_in_range(0..=4)? {
+                0 => {
+                    let opcode = self.opcode(u)?;
+                    self.remaining -= 1;
+                    script.push_opcode(opcode)
+                }
+                1 if self.remaining >= 9 => {
+                    self.remaining -= 9;
+                    // i64::MIN does not fit into the 8 bytes of a script number.
+                    script.push_int(u.int_in_range(i64::MIN + 1..=i64::MAX)?)
+                }
+                2 if self.remaining >= 2 => {
+                    let max_len = MAX_GENERATED_PUSH.min(self.remaining - 2);
+                    let len = u.int_in_range(0..=max_len)?;
+                    let data = u.bytes(len)?.to_vec();
+                    let before = script.len();
+                    // Pushing a Vec<u8> chooses the minimal encoding for single bytes.
+                    script = script.push_expression(data);
+                    self.remaining -= script.len() - before;
+                    script
+                }
+                3 if depth < self.config.max_nesting => {
+                    let reusable: Vec<usize> = (0..self.pool.len())
+                        .filter(|&i| self.pool[i].len() <= self.remaining)
+                        .collect();
+                    if !reusable.is_empty() && self.chance(u, self.config.call_reuse_probability)? {
+                        let called_script = self.pool[*u.choose(&reusable)?].clone();
+                        self.remaining -= called_script.len();
+                        script.push_env_script(called_script)
+                    } else {
+                        let called_script = self.script(u, depth + 1)?;
+                        self.pool.push(called_script.clone());
+                        script.push_env_script(called_script)
+                    }
+                }
+                4 if self.config.flow_control
+                    && depth < self.config.max_nesting
+                    && self.remaining >= 3 =>
+                {
+                    // Reserve the bytes for OP_IF, OP_ELSE and OP_ENDIF up front.
+                    self.remaining -= 3;
+                    let opcode = if bool::arbitrary(u)? { OP_IF } else { OP_NOTIF };
+                    script = script.push_opcode(opcode);
+                    script = script.push_env_script(self.script(u, depth + 1)?);
+                    if bool::arbitrary(u)? {
+                        script = script.push_opcode(OP_ELSE);
+                        script = script.push_env_script(self.script(u, depth + 1)?);
+                    } else {
+                        self.remaining += 1;
+                    }
+                    script.push_opcode(OP_ENDIF)
+                }
+                _ => script,
+            };
+        }
+        Ok(script)
+    }
+}
+
+impl StructuredScript {
+    // Generates a structurally valid script: pushes are minimal, IF blocks are balanced and
+    // every call is registered in the script_map of its caller.
+    pub fn arbitrary_from(
+        u: &mut Unstructured,
+        config: &ArbitraryConfig,
+    ) -> Result<StructuredScript> {
+        let mut generator = Generator {
+            config,
+            remaining: config.max_size,
+            pool: Vec::new(),
+        };
+        generator.script(u, 0)
+    }
+
+    #[cfg(feature = "proptest")]
+    pub fn arbitrary_with(
+        config: ArbitraryConfig,
+    ) -> impl proptest::strategy::Strategy<Value = StructuredScript> {
+        use proptest::prelude::*;
+
+        // Shrinking the raw bytes shrinks the generated script as well.
+        proptest::collection::vec(any::<u8>(), 0..4096).prop_map(move |bytes| {
+            StructuredScript::arbitrary_from(&mut Unstructured::new(&bytes), &config)
+                .unwrap_or_else(|_| StructuredScript::new("arbitrary"))
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for StructuredScript {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        StructuredScript::arbitrary_from(u, &ArbitraryConfig::default())
+    }
+}
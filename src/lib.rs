@@ -1,5 +1,26 @@
+pub mod asm;
+pub mod audit;
+pub mod budget;
 pub mod builder;
+pub mod cost;
+pub mod export;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+pub mod simulator;
+pub mod validation;
 
 pub use crate::builder::StructuredScript as Script;
-pub use script_macro::script;
+pub use crate::simulator::simulate;
+pub use script_macro::{script, script_doc};
 pub use stdext::function_name;
+
+// Builds a script like `script!` but uses the given name with the source location
+// appended as debug identifier, e.g. `my_function [src/scripts.rs:42]`.
+#[macro_export]
+macro_rules! script_named {
+    ($debug_info:expr, $($body:tt)*) => {{
+        let mut script = $crate::script! { $($body)* };
+        script.debug_identifier = format!("{} [{}:{}]", $debug_info, file!(), line!());
+        script
+    }};
+}
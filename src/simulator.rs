@@ -0,0 +1,639 @@
+use crate::builder::StructuredScript;
+use bitcoin::blockdata::opcodes::Opcode;
+use bitcoin::blockdata::script::{read_scriptbool, read_scriptint, write_scriptint, Instruction};
+use bitcoin::hashes::{hash160, ripemd160, sha1, sha256, sha256d, Hash};
+use bitcoin::opcodes::all::*;
+use bitcoin::ScriptBuf;
+use std::fmt;
+
+// Final state of a successful simulation. The last element is the top of the stack.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimResult {
+    pub stack: Vec<Vec<u8>>,
+    pub altstack: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SimErrorKind {
+    StackUnderflow,
+    AltstackUnderflow,
+    // An element that is used as a number is longer than 4 bytes or not minimally encoded.
+    InvalidNumber,
+    // OP_VERIFY or one of the *VERIFY opcodes found a false value.
+    VerifyFailed,
+    OpReturn,
+    // OP_ELSE or OP_ENDIF without OP_IF, or an OP_IF without OP_ENDIF.
+    UnbalancedConditional,
+    // The opcode is not part of the simulated subset or fails when executed.
+    UnsupportedOpcode(Opcode),
+    // A disabled opcode or OP_VERIF/OP_VERNOTIF, which fail even in unexecuted branches.
+    DisabledOpcode(Opcode),
+    // The script contains a push that runs past its end.
+    InvalidInstruction,
+    // OP_CHECKLOCKTIMEVERIFY or OP_CHECKSEQUENCEVERIFY found a negative number.
+    NegativeLocktime,
+}
+
+impl fmt::Display for SimErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimErrorKind::StackUnderflow => write!(f, "stack underflow"),
+            SimErrorKind::AltstackUnderflow => write!(f, "altstack underflow"),
+            SimErrorKind::InvalidNumber => write!(f, "invalid number"),
+            SimErrorKind::VerifyFailed => write!(f, "verify failed"),
+            SimErrorKind::OpReturn => write!(f, "OP_RETURN executed"),
+            SimErrorKind::UnbalancedConditional => write!(f, "unbalanced conditional"),
+            SimErrorKind::UnsupportedOpcode(opcode) => write!(f, "unsupported opcode {}", opcode),
+            SimErrorKind::DisabledOpcode(opcode) => write!(f, "disabled opcode {}", opcode),
+            SimErrorKind::InvalidInstruction => write!(f, "invalid instruction"),
+            SimErrorKind::NegativeLocktime => write!(f, "negative locktime"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SimError {
+    // Byte position of the failing instruction, or the script length if the script ended
+    // with an open OP_IF or in the middle of a push.
+    pub position: usize,
+    pub debug_identifier: String,
+    pub kind: SimErrorKind,
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at position {} in {}",
+            self.kind, self.position, self.debug_identifier
+        )
+    }
+}
+
+// Runs the script on the initial stack (last element on top). Signature checks succeed for
+// every non-empty signature.
+pub fn simulate(script: &StructuredScript, initial: Vec<Vec<u8>>) -> Result<SimResult, SimError> {
+    simulate_with_checksig(script, initial, true)
+}
+
+// Like simulate, but signature checks with a non-empty signature return checksig_succeeds.
+// An empty signature always fails the check, like in a real interpreter.
+pub fn simulate_with_checksig(
+    script: &StructuredScript,
+    initial: Vec<Vec<u8>>,
+    checksig_succeeds: bool,
+) -> Result<SimResult, SimError> {
+    simulate_with_checker(script, initial, &checksig_succeeds)
+}
+
+fn simulate_with_checker(
+    script: &StructuredScript,
+    initial: Vec<Vec<u8>>,
+    checker: &dyn SignatureChecker,
+) -> Result<SimResult, SimError> {
+    let compiled = script.clone().compile();
+    let mut interpreter = Interpreter {
+        stack: initial,
+        altstack: Vec::new(),
+        checker,
+    };
+    match interpreter.run(&compiled) {
+        Ok(()) => Ok(SimResult {
+            stack: interpreter.stack,
+            altstack: interpreter.altstack,
+        }),
+        Err((position, kind)) => {
+            let debug_identifier = if position < script.len() {
+                script.debug_info(position)
+            } else {
+                script.debug_identifier.clone()
+            };
+            Err(SimError {
+                position,
+                debug_identifier,
+                kind,
+            })
+        }
+    }
+}
+
+// Decides the outcome of OP_CHECKSIG and the other signature opcodes. The simulator has no
+// transaction, so the checker has to know the signed message itself. It is never called
+// with an empty signature, which always fails the check.
+pub trait SignatureChecker {
+    fn check_signature(&self, signature: &[u8], pubkey: &[u8]) -> bool;
+}
+
+// Accepts or rejects every signature.
+impl SignatureChecker for bool {
+    fn check_signature(&self, _signature: &[u8], _pubkey: &[u8]) -> bool {
+        *self
+    }
+}
+
+// Accepts every non-empty signature, e.g. to test a script without signing anything.
+#[derive(Clone, Copy, Debug)]
+pub struct AcceptAll;
+
+impl SignatureChecker for AcceptAll {
+    fn check_signature(&self, _signature: &[u8], _pubkey: &[u8]) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SimulationResult {
+    Success(Vec<Vec<u8>>),
+    // The reason and the byte position at which the script failed.
+    Failure(String, usize),
+}
+
+// Runs a script on a witness like a spend would: in addition to the errors of simulate the
+// script fails if it leaves an empty stack or a false value on top of it. Like in witness
+// v0 and tapscript spends, it also fails if more than one element is left, unless the
+// clean stack rule is turned off.
+pub struct ScriptSimulator<'a> {
+    script: &'a StructuredScript,
+    witness: Vec<Vec<u8>>,
+    checker: Box<dyn SignatureChecker + 'a>,
+    require_clean_stack: bool,
+}
+
+impl<'a> ScriptSimulator<'a> {
+    // Every signature check fails unless a checker is set, e.g. AcceptAll. The script has to
+    // leave exactly one element on the stack, otherwise it fails with "<n> elements left on
+    // the stack". See with_clean_stack.
+    pub fn new(script: &'a StructuredScript, witness: Vec<Vec<u8>>) -> Self {
+        ScriptSimulator {
+            script,
+            witness,
+            checker: Box::new(false),
+            require_clean_stack: true,
+        }
+    }
+
+    pub fn with_signature_checker(mut self, checker: impl SignatureChecker + 'a) -> Self {
+        self.checker = Box::new(checker);
+        self
+    }
+
+    // Turn off for legacy scripts, which may leave more elements on the stack.
+    pub fn with_clean_stack(mut self, require_clean_stack: bool) -> Self {
+        self.require_clean_stack = require_clean_stack;
+        self
+    }
+
+    pub fn run(self) -> SimulationResult {
+        match simulate_with_checker(self.script, self.witness, self.checker.as_ref()) {
+            Ok(result) => match result.stack.last() {
+                Some(top) if !read_scriptbool(top) => SimulationResult::Failure(
+                    "false value on top of the stack".to_string(),
+                    self.script.len(),
+                ),
+                None => SimulationResult::Failure("empty stack".to_string(), self.script.len()),
+                Some(_) if self.require_clean_stack && result.stack.len() > 1 => {
+                    SimulationResult::Failure(
+                        format!("{} elements left on the stack", result.stack.len()),
+                        self.script.len(),
+                    )
+                }
+                Some(_) => SimulationResult::Success(result.stack),
+            },
+            Err(error) => SimulationResult::Failure(
+                format!("{} in {}", error.kind, error.debug_identifier),
+                error.position,
+            ),
+        }
+    }
+}
+
+fn encode_num(n: i64) -> Vec<u8> {
+    let mut buf = [0u8; 8];
+    let len = write_scriptint(&mut buf, n);
+    buf[..len].to_vec()
+}
+
+// Reads a minimally encoded number of up to 5 bytes, which OP_CHECKLOCKTIMEVERIFY and
+// OP_CHECKSEQUENCEVERIFY accept instead of the usual 4.
+fn read_locktime(bytes: &[u8]) -> Result<i64, SimErrorKind> {
+    let Some((&last, rest)) = bytes.split_last() else {
+        return Ok(0);
+    };
+    let non_minimal = last & 0x7f == 0 && rest.last().is_none_or(|byte| byte & 0x80 == 0);
+    if bytes.len() > 5 || non_minimal {
+        return Err(SimErrorKind::InvalidNumber);
+    }
+    let magnitude = rest
+        .iter()
+        .rev()
+        .fold((last & 0x7f) as i64, |n, &byte| (n << 8) | byte as i64);
+    Ok(if last & 0x80 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    })
+}
+
+fn encode_bool(b: bool) -> Vec<u8> {
+    if b {
+        vec![1]
+    } else {
+        vec![]
+    }
+}
+
+// Opcodes that make the script invalid wherever they appear.
+fn is_disabled(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        OP_CAT
+            | OP_SUBSTR
+            | OP_LEFT
+            | OP_RIGHT
+            | OP_INVERT
+            | OP_AND
+            | OP_OR
+            | OP_XOR
+            | OP_2MUL
+            | OP_2DIV
+            | OP_MUL
+            | OP_DIV
+            | OP_MOD
+            | OP_LSHIFT
+            | OP_RSHIFT
+            | OP_VERIF
+            | OP_VERNOTIF
+    )
+}
+
+struct Interpreter<'a> {
+    stack: Vec<Vec<u8>>,
+    altstack: Vec<Vec<u8>>,
+    checker: &'a dyn SignatureChecker,
+}
+
+impl Interpreter<'_> {
+    fn pop(&mut self) -> Result<Vec<u8>, SimErrorKind> {
+        self.stack.pop().ok_or(SimErrorKind::StackUnderflow)
+    }
+
+    fn pop_num(&mut self) -> Result<i64, SimErrorKind> {
+        read_scriptint(&self.pop()?).map_err(|_| SimErrorKind::InvalidNumber)
+    }
+
+    fn pop_bool(&mut self) -> Result<bool, SimErrorKind> {
+        Ok(read_scriptbool(&self.pop()?))
+    }
+
+    // Element at depth n, where 0 is the top.
+    fn peek(&self, n: usize) -> Result<&Vec<u8>, SimErrorKind> {
+        if n >= self.stack.len() {
+            return Err(SimErrorKind::StackUnderflow);
+        }
+        Ok(&self.stack[self.stack.len() - 1 - n])
+    }
+
+    fn require(&self, n: usize) -> Result<(), SimErrorKind> {
+        if self.stack.len() < n {
+            return Err(SimErrorKind::StackUnderflow);
+        }
+        Ok(())
+    }
+
+    fn verify(&mut self) -> Result<(), SimErrorKind> {
+        if self.pop_bool()? {
+            Ok(())
+        } else {
+            Err(SimErrorKind::VerifyFailed)
+        }
+    }
+
+    fn checksig(&self, sig: &[u8], pubkey: &[u8]) -> bool {
+        !sig.is_empty() && self.checker.check_signature(sig, pubkey)
+    }
+
+    fn run(&mut self, script: &ScriptBuf) -> Result<(), (usize, SimErrorKind)> {
+        // One entry per open OP_IF, true if its branch that is currently visited is executed
+        let mut conditions: Vec<bool> = Vec::new();
+        for result in script.instruction_indices() {
+            let (position, instruction) =
+                result.map_err(|_| (script.len(), SimErrorKind::InvalidInstruction))?;
+            let executing = conditions.iter().all(|&condition| condition);
+            self.step(instruction, executing, &mut conditions)
+                .map_err(|kind| (position, kind))?;
+        }
+        if !conditions.is_empty() {
+            return Err((script.len(), SimErrorKind::UnbalancedConditional));
+        }
+        Ok(())
+    }
+
+    fn step(
+        &mut self,
+        instruction: Instruction,
+        executing: bool,
+        conditions: &mut Vec<bool>,
+    ) -> Result<(), SimErrorKind> {
+        let opcode = match instruction {
+            Instruction::PushBytes(push_bytes) => {
+                if executing {
+                    self.stack.push(push_bytes.as_bytes().to_vec());
+                }
+                return Ok(());
+            }
+            Instruction::Op(opcode) => opcode,
+        };
+
+        if is_disabled(opcode) {
+            return Err(SimErrorKind::DisabledOpcode(opcode));
+        }
+
+        // Flow control is evaluated in unexecuted branches as well
+        match opcode {
+            OP_IF | OP_NOTIF => {
+                let condition = if executing {
+                    let value = self.pop_bool()?;
+                    if opcode == OP_IF {
+                        value
+                    } else {
+                        !value
+                    }
+                } else {
+                    false
+                };
+                conditions.push(condition);
+                return Ok(());
+            }
+            OP_ELSE => {
+                let condition = conditions
+                    .last_mut()
+                    .ok_or(SimErrorKind::UnbalancedConditional)?;
+                *condition = !*condition;
+                return Ok(());
+            }
+            OP_ENDIF => {
+                conditions
+                    .pop()
+                    .ok_or(SimErrorKind::UnbalancedConditional)?;
+                return Ok(());
+            }
+            _ if !executing => return Ok(()),
+            _ => (),
+        }
+
+        match opcode {
+            OP_PUSHNUM_NEG1 => self.stack.push(encode_num(-1)),
+            _ if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&opcode.to_u8()) => {
+                let n = opcode.to_u8() - OP_PUSHNUM_1.to_u8() + 1;
+                self.stack.push(encode_num(n as i64));
+            }
+            // There is no transaction to compare with, so only the checks on the number itself
+            // are done. The number stays on the stack.
+            OP_CLTV | OP_CSV => {
+                if read_locktime(self.peek(0)?)? < 0 {
+                    return Err(SimErrorKind::NegativeLocktime);
+                }
+            }
+            OP_NOP | OP_NOP1 | OP_NOP4 | OP_NOP5 | OP_NOP6 | OP_NOP7 | OP_NOP8 | OP_NOP9
+            | OP_NOP10 | OP_CODESEPARATOR => (),
+            OP_VERIFY => self.verify()?,
+            OP_RETURN => return Err(SimErrorKind::OpReturn),
+
+            // Stack operations
+            OP_TOALTSTACK => {
+                let item = self.pop()?;
+                self.altstack.push(item);
+            }
+            OP_FROMALTSTACK => {
+                let item = self.altstack.pop().ok_or(SimErrorKind::AltstackUnderflow)?;
+                self.stack.push(item);
+            }
+            OP_2DROP => {
+                self.require(2)?;
+                self.stack.truncate(self.stack.len() - 2);
+            }
+            OP_2DUP => {
+                let (a, b) = (self.peek(1)?.clone(), self.peek(0)?.clone());
+                self.stack.extend([a, b]);
+            }
+            OP_3DUP => {
+                let items = [
+                    self.peek(2)?.clone(),
+                    self.peek(1)?.clone(),
+                    self.peek(0)?.clone(),
+                ];
+                self.stack.extend(items);
+            }
+            OP_2OVER => {
+                let (a, b) = (self.peek(3)?.clone(), self.peek(2)?.clone());
+                self.stack.extend([a, b]);
+            }
+            OP_2ROT => {
+                self.require(6)?;
+                let start = self.stack.len() - 6;
+                let moved: Vec<Vec<u8>> = self.stack.drain(start..start + 2).collect();
+                self.stack.extend(moved);
+            }
+            OP_2SWAP => {
+                self.require(4)?;
+                let start = self.stack.len() - 4;
+                self.stack[start..].rotate_left(2);
+            }
+            OP_IFDUP => {
+                let top = self.peek(0)?.clone();
+                if read_scriptbool(&top) {
+                    self.stack.push(top);
+                }
+            }
+            OP_DEPTH => self.stack.push(encode_num(self.stack.len() as i64)),
+            OP_DROP => {
+                self.pop()?;
+            }
+            OP_DUP => {
+                let top = self.peek(0)?.clone();
+                self.stack.push(top);
+            }
+            OP_NIP => {
+                self.require(2)?;
+                let index = self.stack.len() - 2;
+                self.stack.remove(index);
+            }
+            OP_OVER => {
+                let item = self.peek(1)?.clone();
+                self.stack.push(item);
+            }
+            OP_PICK | OP_ROLL => {
+                let n = self.pop_num()?;
+                if n < 0 || n as usize >= self.stack.len() {
+                    return Err(SimErrorKind::StackUnderflow);
+                }
+                let index = self.stack.len() - 1 - n as usize;
+                let item = if opcode == OP_PICK {
+                    self.stack[index].clone()
+                } else {
+                    self.stack.remove(index)
+                };
+                self.stack.push(item);
+            }
+            OP_ROT => {
+                self.require(3)?;
+                let index = self.stack.len() - 3;
+                let item = self.stack.remove(index);
+                self.stack.push(item);
+            }
+            OP_SWAP => {
+                self.require(2)?;
+                let len = self.stack.len();
+                self.stack.swap(len - 1, len - 2);
+            }
+            OP_TUCK => {
+                self.require(2)?;
+                let top = self.peek(0)?.clone();
+                let index = self.stack.len() - 2;
+                self.stack.insert(index, top);
+            }
+            OP_SIZE => {
+                let size = self.peek(0)?.len();
+                self.stack.push(encode_num(size as i64));
+            }
+
+            // Comparison
+            OP_EQUAL | OP_EQUALVERIFY => {
+                let (b, a) = (self.pop()?, self.pop()?);
+                self.stack.push(encode_bool(a == b));
+                if opcode == OP_EQUALVERIFY {
+                    self.verify()?;
+                }
+            }
+
+            // Arithmetic on one number
+            OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                let a = self.pop_num()?;
+                let result = match opcode {
+                    OP_1ADD => a + 1,
+                    OP_1SUB => a - 1,
+                    OP_NEGATE => -a,
+                    OP_ABS => a.abs(),
+                    OP_NOT => (a == 0) as i64,
+                    _ => (a != 0) as i64,
+                };
+                self.stack.push(encode_num(result));
+            }
+
+            // Arithmetic on two numbers
+            OP_ADD
+            | OP_SUB
+            | OP_BOOLAND
+            | OP_BOOLOR
+            | OP_NUMEQUAL
+            | OP_NUMEQUALVERIFY
+            | OP_NUMNOTEQUAL
+            | OP_LESSTHAN
+            | OP_GREATERTHAN
+            | OP_LESSTHANOREQUAL
+            | OP_GREATERTHANOREQUAL
+            | OP_MIN
+            | OP_MAX => {
+                let (b, a) = (self.pop_num()?, self.pop_num()?);
+                let result = match opcode {
+                    OP_ADD => a + b,
+                    OP_SUB => a - b,
+                    OP_BOOLAND => (a != 0 && b != 0) as i64,
+                    OP_BOOLOR => (a != 0 || b != 0) as i64,
+                    OP_NUMEQUAL | OP_NUMEQUALVERIFY => (a == b) as i64,
+                    OP_NUMNOTEQUAL => (a != b) as i64,
+                    OP_LESSTHAN => (a < b) as i64,
+                    OP_GREATERTHAN => (a > b) as i64,
+                    OP_LESSTHANOREQUAL => (a <= b) as i64,
+                    OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                    OP_MIN => a.min(b),
+                    _ => a.max(b),
+                };
+                self.stack.push(encode_num(result));
+                if opcode == OP_NUMEQUALVERIFY {
+                    self.verify()?;
+                }
+            }
+            OP_WITHIN => {
+                let (max, min, x) = (self.pop_num()?, self.pop_num()?, self.pop_num()?);
+                self.stack.push(encode_bool(min <= x && x < max));
+            }
+
+            // Hashes
+            OP_RIPEMD160 => {
+                let data = self.pop()?;
+                self.stack
+                    .push(ripemd160::Hash::hash(&data).to_byte_array().to_vec());
+            }
+            OP_SHA1 => {
+                let data = self.pop()?;
+                self.stack
+                    .push(sha1::Hash::hash(&data).to_byte_array().to_vec());
+            }
+            OP_SHA256 => {
+                let data = self.pop()?;
+                self.stack
+                    .push(sha256::Hash::hash(&data).to_byte_array().to_vec());
+            }
+            OP_HASH160 => {
+                let data = self.pop()?;
+                self.stack
+                    .push(hash160::Hash::hash(&data).to_byte_array().to_vec());
+            }
+            OP_HASH256 => {
+                let data = self.pop()?;
+                self.stack
+                    .push(sha256d::Hash::hash(&data).to_byte_array().to_vec());
+            }
+
+            // Signature checks, which are decided by the checker
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                let (pubkey, sig) = (self.pop()?, self.pop()?);
+                self.stack.push(encode_bool(self.checksig(&sig, &pubkey)));
+                if opcode == OP_CHECKSIGVERIFY {
+                    self.verify()?;
+                }
+            }
+            OP_CHECKSIGADD => {
+                let pubkey = self.pop()?;
+                let n = self.pop_num()?;
+                let sig = self.pop()?;
+                let success = self.checksig(&sig, &pubkey) as i64;
+                self.stack.push(encode_num(n + success));
+            }
+            OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                let key_count = self.pop_num()?;
+                if !(0..=20).contains(&key_count) {
+                    return Err(SimErrorKind::InvalidNumber);
+                }
+                let mut pubkeys = Vec::new();
+                for _ in 0..key_count {
+                    pubkeys.push(self.pop()?);
+                }
+                let sig_count = self.pop_num()?;
+                if sig_count < 0 || sig_count > key_count {
+                    return Err(SimErrorKind::InvalidNumber);
+                }
+                let mut sigs = Vec::new();
+                for _ in 0..sig_count {
+                    sigs.push(self.pop()?);
+                }
+                // Starting from the top, every signature has to match one of the keys below
+                // the key matched by the previous signature
+                let mut pubkeys = pubkeys.iter();
+                let success = sigs
+                    .iter()
+                    .all(|sig| pubkeys.any(|pubkey| self.checksig(sig, pubkey)));
+                // The extra element consumed because of the off-by-one bug
+                self.pop()?;
+                self.stack.push(encode_bool(success));
+                if opcode == OP_CHECKMULTISIGVERIFY {
+                    self.verify()?;
+                }
+            }
+
+            _ => return Err(SimErrorKind::UnsupportedOpcode(opcode)),
+        }
+        Ok(())
+    }
+}
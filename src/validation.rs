@@ -0,0 +1,576 @@
+use crate::builder::StructuredScript;
+use bitcoin::blockdata::opcodes::{Class, ClassifyContext, Opcode};
+use bitcoin::blockdata::script::Instruction;
+use bitcoin::opcodes::all::*;
+use bitcoin::ScriptBuf;
+use std::fmt;
+
+// Consensus limit for legacy and witness v0 scripts.
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+// Consensus limit for every stack element and thereby every push.
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+// Consensus limit of non-push opcodes in legacy and witness v0 scripts.
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+// Policy limit of signature operations in a P2SH redeem script.
+pub const MAX_P2SH_SIGOPS: usize = 15;
+// Policy limit of signature operations in a transaction, in legacy sigops and in witness
+// sigops (which are not scaled by the witness discount).
+pub const MAX_STANDARD_LEGACY_SIGOPS: usize = 4_000;
+pub const MAX_STANDARD_WITNESS_SIGOPS: usize = 16_000;
+// Policy limit of the data pushed after OP_RETURN.
+pub const MAX_OP_RETURN_PAYLOAD: usize = 80;
+// Tapscript validation weight consumed by every executed signature check, and the
+// budget every input gets on top of its witness size (BIP 342).
+pub const VALIDATION_WEIGHT_PER_SIGOP: usize = 50;
+pub const VALIDATION_WEIGHT_OFFSET: usize = 50;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptValidationContext {
+    // A bare scriptPubKey.
+    Legacy,
+    // A redeem script, which itself has to fit into a single push.
+    P2SH,
+    // A witness script of a witness v0 output.
+    P2WSH,
+    // A tapscript leaf. Tapscript has no size, opcode or static sigop limit.
+    P2TR,
+}
+
+// The context of validate_for.
+pub type ScriptContext = ScriptValidationContext;
+
+impl fmt::Display for ScriptValidationContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ScriptValidationContext::Legacy => "legacy",
+            ScriptValidationContext::P2SH => "p2sh",
+            ScriptValidationContext::P2WSH => "p2wsh",
+            ScriptValidationContext::P2TR => "tapscript",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetworkRule {
+    ScriptSize,
+    PushSize,
+    SigOps,
+    OpCount,
+    OpReturnPayload,
+}
+
+impl fmt::Display for NetworkRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            NetworkRule::ScriptSize => "script size",
+            NetworkRule::PushSize => "push size",
+            NetworkRule::SigOps => "signature operations",
+            NetworkRule::OpCount => "opcode count",
+            NetworkRule::OpReturnPayload => "OP_RETURN payload size",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NetworkRuleViolation {
+    pub rule: NetworkRule,
+    pub value: usize,
+    pub limit: usize,
+}
+
+impl fmt::Display for NetworkRuleViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} exceeds the limit of {}",
+            self.rule, self.value, self.limit
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScriptContextError {
+    // The opcode is disabled or fails the script in this context.
+    InvalidOpcode {
+        opcode: Opcode,
+        position: usize,
+        context: ScriptContext,
+    },
+    // An OP_SUCCESSx opcode makes the tapscript succeed unconditionally.
+    SuccessOpcode {
+        opcode: Opcode,
+        position: usize,
+    },
+    Limit {
+        violation: NetworkRuleViolation,
+        context: ScriptContext,
+    },
+}
+
+impl fmt::Display for ScriptContextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptContextError::InvalidOpcode {
+                opcode,
+                position,
+                context,
+            } => write!(f, "{} at {} is invalid in {}", opcode, position, context),
+            ScriptContextError::SuccessOpcode { opcode, position } => write!(
+                f,
+                "{} at {} is an OP_SUCCESS opcode in tapscript",
+                opcode, position
+            ),
+            ScriptContextError::Limit { violation, context } => {
+                write!(f, "{} in {}", violation, context)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IncompatibilityKind {
+    // OP_CHECKMULTISIG and OP_CHECKMULTISIGVERIFY fail a tapscript.
+    CheckMultisig,
+    // An OP_SUCCESSx opcode makes the tapscript succeed unconditionally.
+    SuccessOpcode,
+    // The position of the last executed OP_CODESEPARATOR is committed to by the signature
+    // hash, so one inside an IF block changes the message depending on the branch taken.
+    CodeSeparatorInBranch,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Incompatibility {
+    pub position: usize,
+    pub debug_identifier: String,
+    pub opcode: Opcode,
+    pub kind: IncompatibilityKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SigBudget {
+    // Signature checks executed by the most expensive path through the script.
+    pub sig_ops: usize,
+    pub required_weight: usize,
+    // Budget provided by the script itself, which is part of the witness.
+    pub available_weight: usize,
+}
+
+impl SigBudget {
+    // Size of the remaining witness elements needed to afford all signature checks.
+    pub fn missing_witness_weight(&self) -> usize {
+        self.required_weight.saturating_sub(self.available_weight)
+    }
+
+    pub fn is_affordable_with(&self, witness_size: usize) -> bool {
+        self.required_weight <= self.available_weight + witness_size
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptType {
+    P2PK,
+    P2PKH,
+    P2SH,
+    P2WPKH,
+    P2WSH,
+    P2TR,
+    OpReturn,
+    Multisig,
+    NonStandard,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConditionProducer {
+    // The condition is pushed as data other than the empty vector or 0x01.
+    Push(Vec<u8>),
+    // The condition is the result of an opcode that does not produce a boolean.
+    Opcode(Opcode),
+    // The condition can not be determined statically, e.g. it is a script input or was
+    // moved by a stack operation.
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MinimalIfWarning {
+    pub position: usize,
+    pub debug_identifier: String,
+    pub producer: ConditionProducer,
+}
+
+// Opcodes that always leave a minimally encoded boolean on the stack.
+fn produces_boolean(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        OP_PUSHNUM_1
+            | OP_EQUAL
+            | OP_NOT
+            | OP_0NOTEQUAL
+            | OP_BOOLAND
+            | OP_BOOLOR
+            | OP_NUMEQUAL
+            | OP_NUMNOTEQUAL
+            | OP_LESSTHAN
+            | OP_GREATERTHAN
+            | OP_LESSTHANOREQUAL
+            | OP_GREATERTHANOREQUAL
+            | OP_WITHIN
+            | OP_CHECKSIG
+            | OP_CHECKMULTISIG
+    )
+}
+
+// Opcodes that leave a value on the stack that is in general not a boolean.
+fn produces_non_boolean(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        OP_PUSHNUM_NEG1
+            | OP_PUSHNUM_2
+            | OP_PUSHNUM_3
+            | OP_PUSHNUM_4
+            | OP_PUSHNUM_5
+            | OP_PUSHNUM_6
+            | OP_PUSHNUM_7
+            | OP_PUSHNUM_8
+            | OP_PUSHNUM_9
+            | OP_PUSHNUM_10
+            | OP_PUSHNUM_11
+            | OP_PUSHNUM_12
+            | OP_PUSHNUM_13
+            | OP_PUSHNUM_14
+            | OP_PUSHNUM_15
+            | OP_PUSHNUM_16
+            | OP_SIZE
+            | OP_DEPTH
+            | OP_1ADD
+            | OP_1SUB
+            | OP_NEGATE
+            | OP_ABS
+            | OP_ADD
+            | OP_SUB
+            | OP_MIN
+            | OP_MAX
+            | OP_RIPEMD160
+            | OP_SHA1
+            | OP_SHA256
+            | OP_HASH160
+            | OP_HASH256
+            | OP_CHECKSIGADD
+    )
+}
+
+// Counts signature operations the way Bitcoin Core does. With accurate counting, which
+// Core uses for redeem and witness scripts, a CHECKMULTISIG preceded by OP_1 to OP_16
+// counts as that many keys. Otherwise, e.g. for bare scriptPubKeys, it counts as 20.
+pub fn count_sigops(script: &ScriptBuf, accurate: bool) -> usize {
+    let mut sigops = 0;
+    let mut last_opcode = None;
+    for instruction in script.instructions().flatten() {
+        if let Instruction::Op(opcode) = instruction {
+            match opcode {
+                OP_CHECKSIG | OP_CHECKSIGVERIFY => sigops += 1,
+                OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                    sigops += match last_opcode {
+                        Some(n)
+                            if accurate
+                                && (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&n) =>
+                        {
+                            (n - OP_PUSHNUM_1.to_u8() + 1) as usize
+                        }
+                        _ => 20,
+                    }
+                }
+                _ => (),
+            }
+            last_opcode = Some(opcode.to_u8());
+        } else {
+            last_opcode = None;
+        }
+    }
+    sigops
+}
+
+// The checks of validate_network_rules on an already compiled script.
+fn network_rule_violations(
+    script: &ScriptBuf,
+    context: ScriptValidationContext,
+) -> Vec<NetworkRuleViolation> {
+    let mut violations = Vec::new();
+    let mut check = |rule, value, limit| {
+        if value > limit {
+            violations.push(NetworkRuleViolation { rule, value, limit });
+        }
+    };
+
+    let size_limit = match context {
+        ScriptValidationContext::Legacy | ScriptValidationContext::P2WSH => Some(MAX_SCRIPT_SIZE),
+        ScriptValidationContext::P2SH => Some(MAX_SCRIPT_ELEMENT_SIZE),
+        ScriptValidationContext::P2TR => None,
+    };
+    if let Some(limit) = size_limit {
+        check(NetworkRule::ScriptSize, script.len(), limit);
+    }
+
+    let mut op_count = 0;
+    for instruction in script.instructions().flatten() {
+        match instruction {
+            Instruction::PushBytes(push_bytes) => check(
+                NetworkRule::PushSize,
+                push_bytes.len(),
+                MAX_SCRIPT_ELEMENT_SIZE,
+            ),
+            Instruction::Op(opcode) if opcode.to_u8() > OP_PUSHNUM_16.to_u8() => op_count += 1,
+            Instruction::Op(_) => (),
+        }
+    }
+    if context != ScriptValidationContext::P2TR {
+        check(NetworkRule::OpCount, op_count, MAX_OPS_PER_SCRIPT);
+    }
+
+    let sigop_limit = match context {
+        ScriptValidationContext::Legacy => Some(MAX_STANDARD_LEGACY_SIGOPS),
+        ScriptValidationContext::P2SH => Some(MAX_P2SH_SIGOPS),
+        ScriptValidationContext::P2WSH => Some(MAX_STANDARD_WITNESS_SIGOPS),
+        ScriptValidationContext::P2TR => None,
+    };
+    if let Some(limit) = sigop_limit {
+        let accurate = context != ScriptValidationContext::Legacy;
+        check(NetworkRule::SigOps, count_sigops(script, accurate), limit);
+    }
+
+    // Only outputs carry OP_RETURN data
+    if context == ScriptValidationContext::Legacy && script.is_op_return() {
+        let payload = script
+            .instructions()
+            .skip(1)
+            .flatten()
+            .map(|instruction| match instruction {
+                Instruction::PushBytes(push_bytes) => push_bytes.len(),
+                Instruction::Op(_) => 0,
+            })
+            .sum();
+        check(NetworkRule::OpReturnPayload, payload, MAX_OP_RETURN_PAYLOAD);
+    }
+
+    violations
+}
+
+impl StructuredScript {
+    // Checks the compiled script against the consensus and standardness limits that apply
+    // in the given context and returns every violated rule.
+    pub fn validate_network_rules(
+        &self,
+        context: ScriptValidationContext,
+    ) -> Vec<NetworkRuleViolation> {
+        network_rule_violations(&self.clone().compile(), context)
+    }
+}
+
+impl StructuredScript {
+    // Checks that every opcode is valid in the given context and that the script stays
+    // within the consensus limits of that context. OP_RETURN is accepted everywhere.
+    pub fn validate_for(&self, context: ScriptContext) -> Result<(), Vec<ScriptContextError>> {
+        let script = self.clone().compile();
+        let classify_context = match context {
+            ScriptContext::Legacy | ScriptContext::P2SH | ScriptContext::P2WSH => {
+                ClassifyContext::Legacy
+            }
+            ScriptContext::P2TR => ClassifyContext::TapScript,
+        };
+        let mut errors = Vec::new();
+        for (position, instruction) in script.instruction_indices().flatten() {
+            let Instruction::Op(opcode) = instruction else {
+                continue;
+            };
+            match opcode.classify(classify_context) {
+                Class::SuccessOp => {
+                    errors.push(ScriptContextError::SuccessOpcode { opcode, position })
+                }
+                Class::IllegalOp => errors.push(ScriptContextError::InvalidOpcode {
+                    opcode,
+                    position,
+                    context,
+                }),
+                Class::ReturnOp if opcode != OP_RETURN => {
+                    errors.push(ScriptContextError::InvalidOpcode {
+                        opcode,
+                        position,
+                        context,
+                    })
+                }
+                _ => (),
+            }
+        }
+
+        errors.extend(
+            network_rule_violations(&script, context)
+                .into_iter()
+                .filter(|violation| {
+                    matches!(
+                        violation.rule,
+                        NetworkRule::ScriptSize | NetworkRule::PushSize | NetworkRule::OpCount
+                    )
+                })
+                .map(|violation| ScriptContextError::Limit { violation, context }),
+        );
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+impl StructuredScript {
+    // Lists the opcodes that behave differently when the script is used as a tapscript leaf.
+    pub fn tapscript_incompatibilities(&self) -> Vec<Incompatibility> {
+        let script = self.clone().compile();
+        let mut incompatibilities = Vec::new();
+        let mut if_depth: usize = 0;
+        for (position, instruction) in script.instruction_indices().flatten() {
+            let Instruction::Op(opcode) = instruction else {
+                continue;
+            };
+            let kind = match opcode {
+                OP_IF | OP_NOTIF => {
+                    if_depth += 1;
+                    None
+                }
+                OP_ENDIF => {
+                    if_depth = if_depth.saturating_sub(1);
+                    None
+                }
+                OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                    Some(IncompatibilityKind::CheckMultisig)
+                }
+                OP_CODESEPARATOR if if_depth > 0 => {
+                    Some(IncompatibilityKind::CodeSeparatorInBranch)
+                }
+                _ if opcode.classify(ClassifyContext::TapScript) == Class::SuccessOp => {
+                    Some(IncompatibilityKind::SuccessOpcode)
+                }
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                incompatibilities.push(Incompatibility {
+                    position,
+                    debug_identifier: self.debug_info(position),
+                    opcode,
+                    kind,
+                });
+            }
+        }
+        incompatibilities
+    }
+
+    // Computes the tapscript validation weight needed for the signature checks. For IF
+    // blocks only the branch with the most signature checks is counted.
+    pub fn sig_budget(&self) -> SigBudget {
+        let script = self.clone().compile();
+        let mut sig_ops = 0;
+        // Signature checks before the IF block and the maximum of its finished branches.
+        let mut branches: Vec<(usize, usize)> = Vec::new();
+        for instruction in script.instructions().flatten() {
+            match instruction {
+                Instruction::Op(OP_CHECKSIG | OP_CHECKSIGVERIFY | OP_CHECKSIGADD) => sig_ops += 1,
+                Instruction::Op(OP_IF | OP_NOTIF) => {
+                    branches.push((sig_ops, 0));
+                    sig_ops = 0;
+                }
+                Instruction::Op(OP_ELSE) => {
+                    if let Some((_, max_branch)) = branches.last_mut() {
+                        *max_branch = (*max_branch).max(sig_ops);
+                        sig_ops = 0;
+                    }
+                }
+                Instruction::Op(OP_ENDIF) => {
+                    if let Some((before, max_branch)) = branches.pop() {
+                        sig_ops = before + max_branch.max(sig_ops);
+                    }
+                }
+                _ => (),
+            }
+        }
+        // Close unterminated IF blocks
+        while let Some((before, max_branch)) = branches.pop() {
+            sig_ops = before + max_branch.max(sig_ops);
+        }
+
+        SigBudget {
+            sig_ops,
+            required_weight: sig_ops * VALIDATION_WEIGHT_PER_SIGOP,
+            available_weight: VALIDATION_WEIGHT_OFFSET + script.len(),
+        }
+    }
+}
+
+impl StructuredScript {
+    // Classifies the compiled script as one of the standard output script templates.
+    pub fn classify_script_type(&self) -> ScriptType {
+        let script = self.clone().compile();
+        if script.is_p2pk() {
+            ScriptType::P2PK
+        } else if script.is_p2pkh() {
+            ScriptType::P2PKH
+        } else if script.is_p2sh() {
+            ScriptType::P2SH
+        } else if script.is_p2wpkh() {
+            ScriptType::P2WPKH
+        } else if script.is_p2wsh() {
+            ScriptType::P2WSH
+        } else if script.is_p2tr() {
+            ScriptType::P2TR
+        } else if script.is_op_return() {
+            ScriptType::OpReturn
+        } else if script.is_multisig() {
+            ScriptType::Multisig
+        } else {
+            ScriptType::NonStandard
+        }
+    }
+
+    pub fn is_standard_output(&self) -> bool {
+        self.classify_script_type() != ScriptType::NonStandard
+    }
+}
+
+impl StructuredScript {
+    // Checks the tapscript MINIMALIF rule: the condition of OP_IF and OP_NOTIF has to be
+    // the empty vector or 0x01. The condition is derived from the instruction right before
+    // the OP_IF, so a warning is not necessarily a violation if the producer is unknown.
+    pub fn check_minimal_if(&self) -> Vec<MinimalIfWarning> {
+        let script = self.clone().compile();
+        let mut warnings = Vec::new();
+        let mut previous = None;
+        for (position, instruction) in script.instruction_indices().flatten() {
+            if let Instruction::Op(OP_IF | OP_NOTIF) = instruction {
+                let producer = match previous {
+                    Some(Instruction::PushBytes(push_bytes))
+                        if push_bytes.is_empty() || push_bytes.as_bytes() == [1] =>
+                    {
+                        None
+                    }
+                    Some(Instruction::PushBytes(push_bytes)) => {
+                        Some(ConditionProducer::Push(push_bytes.as_bytes().to_vec()))
+                    }
+                    Some(Instruction::Op(opcode)) if produces_boolean(opcode) => None,
+                    Some(Instruction::Op(opcode)) if produces_non_boolean(opcode) => {
+                        Some(ConditionProducer::Opcode(opcode))
+                    }
+                    _ => Some(ConditionProducer::Unknown),
+                };
+                if let Some(producer) = producer {
+                    warnings.push(MinimalIfWarning {
+                        position,
+                        debug_identifier: self.debug_info(position),
+                        producer,
+                    });
+                }
+            }
+            previous = Some(instruction);
+        }
+        warnings
+    }
+}
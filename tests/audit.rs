@@ -0,0 +1,126 @@
+use bitcoin::secp256k1::{Secp256k1, SecretKey};
+use bitcoin::PublicKey;
+use bitcoin_script::audit::{FindingKind, FindingSeverity, ScriptAudit};
+use bitcoin_script::script;
+use bitcoin_script::validation::ScriptContext;
+
+fn pubkey(secret: u8) -> PublicKey {
+    let mut secret_key = [0u8; 32];
+    secret_key[31] = secret;
+    PublicKey::new(
+        SecretKey::from_slice(&secret_key)
+            .unwrap()
+            .public_key(&Secp256k1::new()),
+    )
+}
+
+#[test]
+fn test_clean_script() {
+    let script = script! {
+        OP_IF
+            { pubkey(200) }
+        OP_ELSE
+            { pubkey(201) }
+        OP_ENDIF
+        OP_CHECKSIG
+    };
+    assert!(ScriptAudit::default().run(&script).is_empty());
+}
+
+#[test]
+fn test_nested_ifs() {
+    let inner = script! {
+        OP_NOTIF
+            OP_ADD
+        OP_ENDIF
+    };
+    let script = script! {
+        OP_IF
+            OP_DUP
+            { inner }
+        OP_ENDIF
+        OP_IF
+            OP_DROP
+        OP_ENDIF
+    };
+    let findings = ScriptAudit::default().run(&script);
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].kind, FindingKind::NestedIfs);
+    assert_eq!(findings[0].severity, FindingSeverity::Info);
+    assert_eq!(findings[0].byte_offset, 2);
+    assert!(findings[0].message.starts_with("OP_NOTIF at depth 2"));
+}
+
+#[test]
+fn test_hardcoded_key() {
+    let script = script! {
+        { pubkey(100) }
+        OP_CHECKSIGVERIFY
+        { pubkey(1).inner.serialize_uncompressed().to_vec() }
+        OP_CHECKSIG
+    };
+    let findings = ScriptAudit::default().run(&script);
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].kind, FindingKind::HardcodedKey);
+    assert_eq!(findings[0].severity, FindingSeverity::Error);
+    assert_eq!(findings[0].byte_offset, 35);
+    assert!(findings[0]
+        .message
+        .starts_with("public key of the secret key 1 "));
+
+    // x-only keys of tapscript leaves
+    let script = script! {
+        { pubkey(100).inner.x_only_public_key().0 }
+        OP_CHECKSIGVERIFY
+        { pubkey(3).inner.x_only_public_key().0 }
+        OP_CHECKSIG
+    };
+    let findings = ScriptAudit::default().run(&script);
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].kind, FindingKind::HardcodedKey);
+    assert_eq!(findings[0].byte_offset, 34);
+    assert!(findings[0]
+        .message
+        .starts_with("public key of the secret key 3 "));
+}
+
+#[test]
+fn test_excessive_ops() {
+    let audit = ScriptAudit::default();
+    let script = script! {
+        for _ in 0..150 {
+            OP_DUP
+            OP_DROP
+        }
+    };
+    let findings = audit.run(&script);
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].kind, FindingKind::ExcessiveOps);
+    assert_eq!(findings[0].severity, FindingSeverity::Error);
+    assert_eq!(findings[0].byte_offset, audit.max_ops_warning - 1);
+    assert_eq!(findings[0].message, "300 opcodes, the limit is 201");
+
+    // Pushes do not count as opcodes
+    let script = script! {
+        for i in 0..190 {
+            { i }
+            OP_DROP
+        }
+    };
+    let findings = audit.run(&script);
+    assert_eq!(findings.len(), 1);
+    assert_eq!(findings[0].severity, FindingSeverity::Warning);
+    assert!(ScriptAudit {
+        max_ops_warning: 191,
+        ..Default::default()
+    }
+    .run(&script)
+    .is_empty());
+
+    // Tapscript has no opcode limit
+    let audit = ScriptAudit {
+        context: ScriptContext::P2TR,
+        ..Default::default()
+    };
+    assert!(audit.run(&script).is_empty());
+}
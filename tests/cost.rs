@@ -0,0 +1,86 @@
+use bitcoin::blockdata::script::Instruction;
+use bitcoin_script::cost::{CostModel, DefaultCostModel};
+use bitcoin_script::{script, Script};
+
+struct UnitCost;
+
+impl CostModel for UnitCost {
+    fn cost(&self, _instruction: Instruction, _input_size: Option<usize>) -> u64 {
+        1
+    }
+}
+
+fn hash_gadget() -> Script {
+    script! {
+        OP_SHA256
+        OP_SHA256
+        OP_SHA256
+    }
+}
+
+fn branching() -> Script {
+    script! {
+        OP_DUP
+        OP_IF
+            { hash_gadget() }
+        OP_ELSE
+            OP_ADD
+        OP_ENDIF
+        OP_NOTIF
+            OP_DROP
+        OP_ELSE
+            OP_DROP
+            OP_DROP
+        OP_ENDIF
+    }
+}
+
+#[test]
+fn test_estimate_cost_worst_case_branch() {
+    let estimate = branching().estimate_cost(&UnitCost);
+    // OP_DUP OP_IF, 3 hashes, OP_ELSE OP_ENDIF and OP_NOTIF OP_ELSE, 2 drops, OP_ENDIF
+    assert_eq!(estimate.total, 12);
+    assert_eq!(estimate.by_identifier.values().sum::<u64>(), 12);
+
+    let ranking = estimate.ranking();
+    assert_eq!(ranking.len(), 2);
+    assert!(ranking[0].0.contains("branching"));
+    assert_eq!(ranking[0].1, 9);
+    assert!(ranking[1].0.contains("hash_gadget"));
+    assert_eq!(ranking[1].1, 3);
+}
+
+#[test]
+fn test_default_cost_model() {
+    let model = DefaultCostModel::default();
+    let script = script! {
+        { vec![0x01u8; 100] }
+        OP_SHA256
+        OP_SHA256
+        OP_CHECKSIG
+    };
+    // The first hash reads the 100 byte push, the second one its 32 byte output
+    let estimate = script.estimate_cost(&model);
+    assert_eq!(estimate.total, 4 + 20 + 10 + 50);
+
+    // The hashes dominate the worst case
+    let estimate = branching().estimate_cost(&model);
+    assert_eq!(estimate.ranking()[0].1, 20 + 10 + 10);
+    assert!(estimate.ranking()[0].0.contains("hash_gadget"));
+}
+
+#[test]
+fn test_chained_hash_cost() {
+    let model = DefaultCostModel::default();
+    let script = script! {
+        { vec![0x01u8; 200] }
+        OP_SHA256
+        OP_HASH160
+        OP_RIPEMD160
+        OP_ADD
+        OP_SHA256
+    };
+    // 32 and 20 byte outputs fit into a single block, the last hash reads an unknown input
+    let estimate = script.estimate_cost(&model);
+    assert_eq!(estimate.total, 7 + 40 + 10 + 10 + 1 + 20);
+}
@@ -0,0 +1,65 @@
+#![cfg(feature = "proptest")]
+
+use bitcoin_script::{builder::DfsItem, fuzz::ArbitraryConfig, Script};
+use proptest::prelude::*;
+
+fn config() -> ArbitraryConfig {
+    ArbitraryConfig {
+        max_size: 1000,
+        max_nesting: 5,
+        call_reuse_probability: 0.7,
+        flow_control: true,
+    }
+}
+
+proptest! {
+    #[test]
+    fn compile_never_panics(script in Script::arbitrary_with(config())) {
+        let len = script.len();
+        prop_assert!(len <= config().max_size);
+        prop_assert_eq!(script.compile().len(), len);
+    }
+
+    #[test]
+    fn compile_matches_flattened_script(script in Script::arbitrary_with(config())) {
+        let flattened: Vec<u8> = script
+            .depth_first_iter()
+            .filter_map(|item| match item {
+                DfsItem::ScriptBuf { buf, .. } => Some(buf.as_bytes().to_vec()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        prop_assert_eq!(script.compile().to_bytes(), flattened);
+    }
+
+    #[test]
+    fn flow_control_is_balanced(script in Script::arbitrary_with(config())) {
+        let mut open_ifs = 0i64;
+        for instruction in script.compile().instructions() {
+            if let Ok(bitcoin::script::Instruction::Op(opcode)) = instruction {
+                match opcode {
+                    bitcoin::opcodes::all::OP_IF | bitcoin::opcodes::all::OP_NOTIF => open_ifs += 1,
+                    bitcoin::opcodes::all::OP_ENDIF => open_ifs -= 1,
+                    _ => (),
+                }
+                prop_assert!(open_ifs >= 0);
+            }
+        }
+        prop_assert_eq!(open_ifs, 0);
+    }
+}
+
+// i64::MIN has no script number encoding and must not make the generator panic
+#[test]
+fn generate_from_extreme_bytes() {
+    use arbitrary::{Arbitrary, Unstructured};
+    let min = i64::MIN.to_le_bytes();
+    for first in 0..=255u8 {
+        for second in 0..=255u8 {
+            let mut data = vec![first, second];
+            data.extend(min.iter().cycle().take(64));
+            let _ = Script::arbitrary(&mut Unstructured::new(&data));
+        }
+    }
+}
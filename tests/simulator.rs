@@ -0,0 +1,380 @@
+use bitcoin::hashes::{hash160, sha256, Hash};
+use bitcoin::opcodes::all::{OP_CAT, OP_CSV, OP_EQUALVERIFY, OP_INVALIDOPCODE, OP_VERIF};
+use bitcoin::secp256k1::{ecdsa, Message, PublicKey, Secp256k1, SecretKey};
+use bitcoin_script::simulator::{
+    simulate_with_checksig, AcceptAll, ScriptSimulator, SignatureChecker, SimErrorKind, SimResult,
+    SimulationResult,
+};
+use bitcoin_script::{script, simulate, Script};
+
+fn num(n: i64) -> Vec<u8> {
+    let mut buf = [0u8; 8];
+    let len = bitcoin::script::write_scriptint(&mut buf, n);
+    buf[..len].to_vec()
+}
+
+fn add_and_double() -> Script {
+    script! {
+        OP_ADD
+        OP_DUP
+        OP_ADD
+    }
+}
+
+#[test]
+fn test_arithmetic() {
+    let result = simulate(&add_and_double(), vec![num(3), num(4)]).unwrap();
+    assert_eq!(
+        result,
+        SimResult {
+            stack: vec![num(14)],
+            altstack: vec![]
+        }
+    );
+
+    let script = script! {
+        OP_TOALTSTACK
+        OP_2DUP
+        OP_SUB
+        OP_ROT
+        OP_ROT
+        OP_MAX
+        -5
+        OP_ABS
+        OP_WITHIN
+        OP_FROMALTSTACK
+        OP_1ADD
+        1
+        OP_PICK
+    };
+    let result = simulate(&script, vec![num(10), num(7), num(100)]).unwrap();
+    assert_eq!(result.stack, vec![vec![], num(101), vec![]]);
+    assert!(result.altstack.is_empty());
+
+    // The sum is 5 bytes long and can not be used as a number again
+    let script = script! {
+        OP_ADD
+        OP_1ADD
+    };
+    let error = simulate(&script, vec![num(i32::MAX as i64), num(i32::MAX as i64)]).unwrap_err();
+    assert_eq!(error.kind, SimErrorKind::InvalidNumber);
+    assert_eq!(error.position, 1);
+}
+
+#[test]
+fn test_stack_underflow() {
+    let script = script! {
+        OP_1
+        { add_and_double() }
+    };
+    let error = simulate(&script, vec![]).unwrap_err();
+    assert_eq!(error.kind, SimErrorKind::StackUnderflow);
+    assert_eq!(error.position, 1);
+    assert!(error.debug_identifier.contains("add_and_double"));
+    assert!(error
+        .to_string()
+        .starts_with("stack underflow at position 1"));
+}
+
+fn htlc(hash: &[u8], timeout: i64) -> Script {
+    script! {
+        OP_IF
+            OP_SHA256
+            { hash.to_vec() }
+            OP_EQUALVERIFY
+            { vec![0x02u8; 33] }
+        OP_ELSE
+            { timeout }
+            OP_CSV
+            OP_DROP
+            { vec![0x03u8; 33] }
+        OP_ENDIF
+        OP_CHECKSIG
+    }
+}
+
+#[test]
+fn test_htlc() {
+    let preimage = vec![0x42u8; 32];
+    let hash = sha256::Hash::hash(&preimage).to_byte_array();
+    let script = htlc(&hash, 144);
+    let sig = vec![0x30u8; 71];
+
+    // Hash lock path
+    let result = simulate(&script, vec![sig.clone(), preimage.clone(), num(1)]).unwrap();
+    assert_eq!(result.stack, vec![num(1)]);
+
+    // Time lock path
+    let result = simulate(&script, vec![sig.clone(), vec![]]).unwrap();
+    assert_eq!(result.stack, vec![num(1)]);
+
+    // Wrong preimage
+    let error = simulate(&script, vec![sig.clone(), vec![0u8; 32], num(1)]).unwrap_err();
+    assert_eq!(error.kind, SimErrorKind::VerifyFailed);
+    assert_eq!(
+        script.clone().compile().as_bytes()[error.position],
+        OP_EQUALVERIFY.to_u8()
+    );
+
+    // Failing signature check
+    let result = simulate_with_checksig(&script, vec![sig, vec![]], false).unwrap();
+    assert_eq!(result.stack, vec![vec![]]);
+    let result = simulate(&script, vec![vec![], vec![]]).unwrap();
+    assert_eq!(result.stack, vec![vec![]]);
+}
+
+#[test]
+fn test_locktime_checks() {
+    for script in [script! { OP_CLTV }, script! { OP_CSV }] {
+        let error = simulate(&script, vec![]).unwrap_err();
+        assert_eq!(error.kind, SimErrorKind::StackUnderflow);
+    }
+
+    // A negative timeout fails the time lock path
+    let script = htlc(&[0u8; 32], -1);
+    let error = simulate(&script, vec![vec![0x30u8; 71], vec![]]).unwrap_err();
+    assert_eq!(error.kind, SimErrorKind::NegativeLocktime);
+    assert_eq!(
+        script.clone().compile().as_bytes()[error.position],
+        OP_CSV.to_u8()
+    );
+
+    // Locktimes may have 5 bytes and stay on the stack
+    let script = script! { OP_CLTV };
+    let result = simulate(&script, vec![num(0xffff_ffff)]).unwrap();
+    assert_eq!(result.stack, vec![num(0xffff_ffff)]);
+    let error = simulate(&script, vec![num(-0xffff_ffff)]).unwrap_err();
+    assert_eq!(error.kind, SimErrorKind::NegativeLocktime);
+    for invalid in [vec![0xff; 6], vec![0x01, 0x00]] {
+        let error = simulate(&script, vec![invalid]).unwrap_err();
+        assert_eq!(error.kind, SimErrorKind::InvalidNumber);
+    }
+}
+
+#[test]
+fn test_unbalanced_and_unsupported() {
+    let script = script! {
+        OP_1
+        OP_IF
+        OP_1
+    };
+    let error = simulate(&script, vec![]).unwrap_err();
+    assert_eq!(error.kind, SimErrorKind::UnbalancedConditional);
+    assert_eq!(error.position, script.len());
+
+    let script = script! {
+        OP_0
+        OP_IF
+            OP_CAT
+        OP_ELSE
+            OP_RETURN
+        OP_ENDIF
+    };
+    // Disabled opcodes fail the script even in an unexecuted branch
+    let error = simulate(&script, vec![]).unwrap_err();
+    assert_eq!(error.kind, SimErrorKind::DisabledOpcode(OP_CAT));
+    assert_eq!(error.position, 2);
+    let script = script! {
+        OP_0
+        OP_IF
+            OP_VERIF
+        OP_ENDIF
+        OP_1
+    };
+    assert_eq!(
+        simulate(&script, vec![]).unwrap_err().kind,
+        SimErrorKind::DisabledOpcode(OP_VERIF)
+    );
+
+    let script = script! {
+        OP_0
+        OP_IF
+            OP_CHECKSIGADD
+        OP_ELSE
+            OP_RETURN
+        OP_ENDIF
+    };
+    assert_eq!(
+        simulate(&script, vec![]).unwrap_err().kind,
+        SimErrorKind::OpReturn
+    );
+    let script = script! {
+        OP_1
+        OP_SUB
+        OP_NOP
+        OP_CAT
+    };
+    let error = simulate(&script, vec![num(1)]).unwrap_err();
+    assert_eq!(error.kind, SimErrorKind::DisabledOpcode(OP_CAT));
+    assert_eq!(error.position, 3);
+    let script = script! {
+        OP_1
+        OP_CLTV
+        OP_INVALIDOPCODE
+    };
+    assert_eq!(
+        simulate(&script, vec![]).unwrap_err().kind,
+        SimErrorKind::UnsupportedOpcode(OP_INVALIDOPCODE)
+    );
+}
+
+#[test]
+fn test_range_check() {
+    let script = Script::new("range").push_range_check(-5, 300);
+    for x in [-5, 0, 300] {
+        let result = simulate(&script, vec![vec![0x42], num(x)]).unwrap();
+        assert_eq!(result.stack, vec![vec![0x42], num(x)]);
+    }
+    for x in [-6, 301] {
+        let error = simulate(&script, vec![num(x)]).unwrap_err();
+        assert_eq!(error.kind, SimErrorKind::VerifyFailed);
+    }
+
+    let script = Script::new("range").push_range_check_consuming(7, 7);
+    let result = simulate(&script, vec![vec![0x42], num(7)]).unwrap();
+    assert_eq!(result.stack, vec![vec![0x42]]);
+    for x in [6, 8] {
+        assert!(simulate(&script, vec![num(x)]).is_err());
+    }
+}
+
+#[test]
+#[should_panic(expected = "Empty range: 2 > 1")]
+fn test_range_check_empty_range() {
+    Script::new("range").push_range_check(2, 1);
+}
+
+// Verifies ECDSA signatures with a sighash type byte over a fixed message.
+struct MessageChecker(Message);
+
+impl SignatureChecker for MessageChecker {
+    fn check_signature(&self, signature: &[u8], pubkey: &[u8]) -> bool {
+        let (Ok(signature), Ok(pubkey)) = (
+            ecdsa::Signature::from_der(&signature[..signature.len() - 1]),
+            PublicKey::from_slice(pubkey),
+        ) else {
+            return false;
+        };
+        Secp256k1::verification_only()
+            .verify_ecdsa(&self.0, &signature, &pubkey)
+            .is_ok()
+    }
+}
+
+fn keys_and_signatures(count: u8, message: &Message) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let secp = Secp256k1::new();
+    (1..=count)
+        .map(|i| {
+            let secret_key = SecretKey::from_slice(&[i; 32]).unwrap();
+            let mut signature = secp
+                .sign_ecdsa(message, &secret_key)
+                .serialize_der()
+                .to_vec();
+            signature.push(0x01);
+            let pubkey = PublicKey::from_secret_key(&secp, &secret_key).serialize();
+            (pubkey.to_vec(), signature)
+        })
+        .collect()
+}
+
+#[test]
+fn test_script_simulator_p2pkh() {
+    let message = Message::from_digest([0x17; 32]);
+    let (pubkey, signature) = keys_and_signatures(1, &message).remove(0);
+    let script = script! {
+        OP_DUP
+        OP_HASH160
+        { hash160::Hash::hash(&pubkey).to_byte_array().to_vec() }
+        OP_EQUALVERIFY
+        OP_CHECKSIG
+    };
+
+    let result = ScriptSimulator::new(&script, vec![signature.clone(), pubkey.clone()])
+        .with_signature_checker(MessageChecker(message))
+        .run();
+    assert_eq!(result, SimulationResult::Success(vec![num(1)]));
+
+    let other_message = Message::from_digest([0x18; 32]);
+    let result = ScriptSimulator::new(&script, vec![signature.clone(), pubkey.clone()])
+        .with_signature_checker(MessageChecker(other_message))
+        .run();
+    assert!(matches!(result, SimulationResult::Failure(_, position) if position == script.len()));
+
+    let result = ScriptSimulator::new(&script, vec![signature, vec![0x02; 33]])
+        .with_signature_checker(AcceptAll)
+        .run();
+    let SimulationResult::Failure(reason, position) = result else {
+        panic!("Simulation with a wrong key succeeded");
+    };
+    assert!(reason.starts_with("verify failed"));
+    assert_eq!(position, 23);
+
+    // Without a checker any signature fails the check
+    let garbage = vec![0x30; 72];
+    let result = ScriptSimulator::new(&script, vec![garbage.clone(), pubkey.clone()]).run();
+    assert_eq!(
+        result,
+        SimulationResult::Failure("false value on top of the stack".to_string(), 25)
+    );
+    let result = ScriptSimulator::new(&script, vec![garbage, pubkey])
+        .with_signature_checker(AcceptAll)
+        .run();
+    assert_eq!(result, SimulationResult::Success(vec![num(1)]));
+}
+
+#[test]
+fn test_script_simulator_multisig() {
+    let message = Message::from_digest([0x17; 32]);
+    let keys = keys_and_signatures(3, &message);
+    let script = script! {
+        OP_2
+        for (pubkey, _) in &keys {
+            { pubkey.clone() }
+        }
+        OP_3
+        OP_CHECKMULTISIG
+    };
+    let run = |witness: Vec<Vec<u8>>| {
+        ScriptSimulator::new(&script, witness)
+            .with_signature_checker(MessageChecker(message))
+            .run()
+    };
+
+    let witness = vec![vec![], keys[0].1.clone(), keys[2].1.clone()];
+    assert_eq!(run(witness), SimulationResult::Success(vec![num(1)]));
+    let witness = vec![vec![], keys[1].1.clone(), keys[2].1.clone()];
+    assert_eq!(run(witness), SimulationResult::Success(vec![num(1)]));
+
+    // The signatures have to be in the order of the keys
+    let witness = vec![vec![], keys[2].1.clone(), keys[0].1.clone()];
+    assert!(matches!(run(witness), SimulationResult::Failure(..)));
+    let witness = vec![vec![], keys[0].1.clone(), keys[0].1.clone()];
+    assert!(matches!(run(witness), SimulationResult::Failure(..)));
+}
+
+#[test]
+fn test_script_simulator_clean_stack() {
+    let script = script! {
+        OP_DUP
+        OP_EQUAL
+    };
+    let result = ScriptSimulator::new(&script, vec![num(5)]).run();
+    assert_eq!(result, SimulationResult::Success(vec![num(1)]));
+
+    // The element below the result is left over
+    let result = ScriptSimulator::new(&script, vec![num(7), num(5)]).run();
+    assert_eq!(
+        result,
+        SimulationResult::Failure("2 elements left on the stack".to_string(), 2)
+    );
+    let result = ScriptSimulator::new(&script, vec![num(7), num(5)])
+        .with_clean_stack(false)
+        .run();
+    assert_eq!(result, SimulationResult::Success(vec![num(7), num(1)]));
+
+    let result = ScriptSimulator::new(&script! { OP_DROP }, vec![num(1)]).run();
+    assert_eq!(
+        result,
+        SimulationResult::Failure("empty stack".to_string(), 1)
+    );
+}
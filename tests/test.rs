@@ -1,9 +1,20 @@
 use bitcoin::{
     consensus::{encode, Encodable},
-    opcodes::all::OP_ADD,
-    Witness,
+    hex::DisplayHex,
+    opcodes::all::{
+        OP_ADD, OP_CHECKSIG, OP_DUP, OP_EQUALVERIFY, OP_HASH160, OP_NOP, OP_PUSHBYTES_20,
+        OP_PUSHNUM_16, OP_PUSHNUM_5, OP_PUSHNUM_NEG1,
+    },
+    secp256k1::{Keypair, Message, Secp256k1, SecretKey},
+    taproot::{LeafVersion, TapLeafHash, TapNodeHash},
+    Address, EcdsaSighashType, Network, TapSighashType, Witness, XOnlyPublicKey,
 };
-use bitcoin_script::{script, Script};
+use bitcoin_script::{
+    budget::BudgetedScript,
+    builder::{Block, CompactBlock, CompactScript, DfsItem, ScriptBuilder, ScriptId},
+    script, script_doc, script_named, Script,
+};
+use std::time::Instant;
 
 #[test]
 fn test_generic() {
@@ -150,6 +161,915 @@ fn test_if() {
     assert_eq!(script.compile().to_bytes(), vec![83, 85]);
 }
 
+#[test]
+fn test_match() {
+    for (x, expected) in [(0, vec![81]), (1, vec![82, 83]), (7, vec![84])] {
+        let script = script! {
+            match x {
+                0 => { OP_1 },
+                1 | 2 => {
+                    OP_2
+                    OP_3
+                }
+                _ => { OP_4 }
+            }
+        };
+        assert_eq!(script.compile().to_bytes(), expected);
+    }
+}
+
+#[test]
+fn test_mul() {
+    let script = script! { OP_ADD } * 3;
+    assert_eq!(script.len(), 3);
+    // The first repetition is inlined
+    assert!(matches!(script.blocks[0], Block::Script(_)));
+    assert_eq!(called_ids(&script).len(), 2);
+    assert_eq!(script.compile(), script! { OP_ADD OP_ADD OP_ADD }.compile());
+
+    for times in 0..3 {
+        let script = script! { OP_1 OP_ADD } * times;
+        assert_eq!(script.len(), 2 * times);
+        assert_eq!(script.compile().to_bytes(), [81, 147].repeat(times));
+    }
+
+    let mut script = script! { OP_DUP OP_ADD };
+    script *= 2;
+    script *= 2;
+    assert_eq!(script.compile().to_bytes(), [118, 147].repeat(4));
+}
+
+#[test]
+fn test_mark_coverage_points() {
+    let script = script! {
+        for _ in 0..10 {
+            OP_ADD
+        }
+    };
+    let (marked, positions) = script.mark_coverage_points(3);
+    assert_eq!(positions, vec![0, 3, 6, 9]);
+    // Every marker is a single byte push of its identifier followed by OP_DROP.
+    assert_eq!(marked.len(), script.len() + 2 * positions.len());
+    assert_eq!(
+        marked.compile().to_bytes(),
+        vec![0, 117, 147, 147, 147, 81, 117, 147, 147, 147, 82, 117, 147, 147, 147, 83, 117, 147]
+    );
+
+    let bytes = vec![0xabu8; 40];
+    let script = script! {
+        OP_DUP
+        { bytes }
+        OP_EQUAL
+    };
+    let (marked, positions) = script.mark_coverage_points(1);
+    assert_eq!(positions, vec![0, 1, 42]);
+    assert_eq!(marked.len(), script.len() + 6);
+}
+
+#[test]
+fn test_array_export() {
+    let script = script! {
+        for _ in 0..20 {
+            OP_ADD
+        }
+        0xabcd
+    };
+
+    let c_array = script.to_c_array("script");
+    assert!(c_array.starts_with("static const uint8_t script[] = {\n"));
+    assert!(c_array.ends_with(",\n};\n"));
+    assert_eq!(c_array.matches("0x").count(), 24);
+    assert_eq!(
+        c_array.lines().nth(1).unwrap(),
+        format!("    {},", ["0x93"; 16].join(", "))
+    );
+    assert_eq!(
+        c_array.lines().nth(2).unwrap(),
+        "    0x93, 0x93, 0x93, 0x93, 0x03, 0xcd, 0xab, 0x00,"
+    );
+
+    let rust_array = script.to_rust_array("SCRIPT");
+    assert!(rust_array.starts_with("const SCRIPT: &[u8] = &[\n"));
+    assert!(rust_array.ends_with(",\n];\n"));
+    assert_eq!(rust_array.matches("0x").count(), 24);
+    // Both use the same byte formatting.
+    assert_eq!(
+        c_array.lines().skip(1).collect::<Vec<_>>()[..2],
+        rust_array.lines().skip(1).collect::<Vec<_>>()[..2]
+    );
+}
+
+#[test]
+fn test_stable_hashes() {
+    let inner = script! { OP_ADD };
+    let script = script! {
+        OP_1
+        { inner }
+    };
+
+    // Fixed values so that the hashes stay stable across processes and Rust versions.
+    assert_eq!(
+        script.content_hash().to_lower_hex_string(),
+        "fd5f2baf36a4b89deb49b1abd19102f3b24f89dfadcb9f567d667e30068fc270"
+    );
+    assert_eq!(
+        script.structure_hash().to_lower_hex_string(),
+        "2d3238d6fe0052c6e50895b7047e4ebbccd4af13c8629a7cb745c5e8071ec5f5"
+    );
+
+    // A flat script with the same bytes has the same content but a different structure.
+    let flat = script! { OP_1 OP_ADD };
+    assert_eq!(flat.content_hash(), script.content_hash());
+    assert_ne!(flat.structure_hash(), script.structure_hash());
+}
+
+#[test]
+fn test_script_named() {
+    let script = script_named!("my_script", OP_ADD OP_DUP);
+    assert!(script
+        .debug_identifier
+        .starts_with("my_script [tests/test.rs:"));
+    assert_eq!(script.debug_info(1), script.debug_identifier);
+    assert_eq!(script.compile().to_bytes(), vec![147, 118]);
+}
+
+#[test]
+#[cfg(feature = "script-debug")]
+fn test_script_debug_location() {
+    let script = script! { OP_ADD };
+    assert!(script
+        .debug_identifier
+        .contains("test_script_debug_location"));
+    assert!(script.debug_identifier.contains("[tests/test.rs:"));
+}
+
+#[test]
+fn test_push_signatures() {
+    let secp = Secp256k1::new();
+    let secret_key = SecretKey::from_slice(&[0x42; 32]).unwrap();
+    let message = Message::from_digest([0x01; 32]);
+
+    let sig = bitcoin::ecdsa::Signature {
+        signature: secp.sign_ecdsa(&message, &secret_key),
+        sighash_type: EcdsaSighashType::All,
+    };
+    let mut expected = sig.signature.serialize_der().to_vec();
+    expected.push(0x01);
+    let script = Script::new("sig").push_der_sig(&sig);
+    assert_eq!(
+        script.compile().as_bytes(),
+        [vec![expected.len() as u8], expected].concat()
+    );
+
+    let keypair = Keypair::from_secret_key(&secp, &secret_key);
+    let sig = bitcoin::taproot::Signature {
+        signature: secp.sign_schnorr_no_aux_rand(&message, &keypair),
+        sighash_type: TapSighashType::Default,
+    };
+    let script = Script::new("sig").push_schnorr_sig(&sig);
+    assert_eq!(
+        script.compile().as_bytes(),
+        [vec![64], sig.signature.as_ref().to_vec()].concat()
+    );
+
+    let sig = bitcoin::taproot::Signature {
+        sighash_type: TapSighashType::Single,
+        ..sig
+    };
+    let script = Script::new("sig").push_schnorr_sig(&sig);
+    assert_eq!(
+        script.compile().as_bytes(),
+        [vec![65], sig.signature.as_ref().to_vec(), vec![0x03]].concat()
+    );
+}
+
+#[test]
+fn test_script_builder_rollback() {
+    let mut builder = ScriptBuilder::new("builder");
+    builder.push_opcode(OP_ADD).push_int(1000);
+    let checkpoint = builder.checkpoint();
+    let expected = builder.script().clone().compile();
+
+    // Rolling back inside a script block and after a call.
+    builder
+        .push_opcode(OP_ADD)
+        .push_env_script(script! { OP_DUP OP_ADD })
+        .push_int(5);
+    assert_eq!(builder.len(), checkpoint + 4);
+    builder.rollback_to(checkpoint);
+    assert_eq!(builder.len(), checkpoint);
+    assert_eq!(builder.script().clone().compile(), expected);
+
+    builder.push_env_script(script! { OP_DUP });
+    let second_checkpoint = builder.checkpoint();
+    builder.push_opcode(OP_ADD);
+    builder.rollback_to(second_checkpoint);
+    assert_eq!(builder.len(), second_checkpoint);
+
+    builder.rollback_to(0);
+    assert!(builder.is_empty());
+    builder.push_opcode(OP_ADD);
+    assert_eq!(builder.finish().compile().to_bytes(), vec![147]);
+}
+
+fn big_gadget() -> Script {
+    script! {
+        for _ in 0..1500 {
+            OP_ADD
+        }
+    }
+}
+
+#[test]
+fn test_budgeted_script() {
+    let script = BudgetedScript::new("budgeted", 4000)
+        .push_env_script(big_gadget())
+        .unwrap()
+        .push_env_script(big_gadget())
+        .unwrap()
+        .push_opcode(OP_ADD)
+        .unwrap();
+    // Analysis methods are available through Deref.
+    assert_eq!(script.len(), 3001);
+
+    let err = script.clone().push_env_script(big_gadget()).unwrap_err();
+    assert_eq!((err.current_size, err.budget), (4501, 4000));
+    assert_eq!(err.added_by, big_gadget().debug_identifier);
+    // The script without the rejected push is returned
+    assert!(err.script.compiled_eq(&script));
+
+    // Scripts in expressions are reported by their identifier, other pushes by their asm
+    let err = script.clone().push_expression(big_gadget()).unwrap_err();
+    assert_eq!(err.added_by, big_gadget().debug_identifier);
+    let err = script
+        .clone()
+        .push_expression(vec![big_gadget(), big_gadget()])
+        .unwrap_err();
+    assert_eq!(
+        err.added_by,
+        format!("{0}, {0}", big_gadget().debug_identifier)
+    );
+
+    let script = script.push_expression(vec![0u8; 994]).unwrap();
+    assert_eq!(script.len(), 4000 - 2);
+    let script = script
+        .push_opcode(OP_ADD)
+        .unwrap()
+        .push_opcode(OP_ADD)
+        .unwrap();
+    let err = script.clone().push_int(1).unwrap_err();
+    assert_eq!(err.added_by, "OP_PUSHNUM_1");
+    let err = script.clone().push_opcode(OP_ADD).unwrap_err();
+    assert_eq!(err.added_by, "OP_ADD");
+    assert_eq!(err.script.len(), 4000);
+
+    // An empty script keeps its identifier if the first push is rejected
+    let err = BudgetedScript::new("budgeted", 1000)
+        .push_env_script(big_gadget())
+        .unwrap_err();
+    assert!(err.script.is_empty());
+    assert_eq!(err.script.debug_identifier, "budgeted");
+}
+
+#[test]
+fn test_cfg_block() {
+    let debug_assertion = script! {
+        OP_DUP
+        { 10 }
+        OP_LESSTHAN
+        OP_VERIFY
+    };
+    let script = script! {
+        OP_ADD
+        #[cfg(feature = "script-debug")]
+        {
+            OP_DUP
+            { 10 }
+            OP_LESSTHAN
+            OP_VERIFY
+        }
+        #[cfg(not(feature = "script-debug"))]
+        {
+        }
+    };
+    let expected_size = if cfg!(feature = "script-debug") {
+        1 + debug_assertion.len()
+    } else {
+        1
+    };
+    assert_eq!(script.len(), expected_size);
+}
+
+#[test]
+fn test_p2tr_address() {
+    let secp = Secp256k1::new();
+    let secret_key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+    let internal_key = XOnlyPublicKey::from(secret_key.public_key(&secp));
+    let script = script! {
+        OP_SHA256
+        { vec![0x42u8; 32] }
+        OP_EQUAL
+    };
+
+    let leaf_hash = TapLeafHash::from_script(&script.clone().compile(), LeafVersion::TapScript);
+    let expected = Address::p2tr(
+        &secp,
+        internal_key,
+        Some(TapNodeHash::from(leaf_hash)),
+        Network::Regtest,
+    );
+    assert_eq!(
+        script.p2tr_address(Network::Regtest, internal_key),
+        expected
+    );
+
+    let spend_info = script.clone().as_taproot_spend_info(internal_key);
+    assert_eq!(spend_info.internal_key(), internal_key);
+    assert_eq!(spend_info.merkle_root(), Some(TapNodeHash::from(leaf_hash)));
+    assert!(spend_info
+        .control_block(&(script.compile(), LeafVersion::TapScript))
+        .is_some());
+}
+
+#[test]
+fn test_execution_path_count() {
+    let script = script! {
+        OP_ADD
+        OP_DUP
+    };
+    assert_eq!(script.execution_path_count(), 1);
+
+    let single = script! {
+        OP_IF
+            OP_ADD
+        OP_ELSE
+            OP_SUB
+        OP_ENDIF
+    };
+    assert_eq!(single.execution_path_count(), 2);
+
+    let nested = script! {
+        OP_NOTIF
+            { single.clone() }
+        OP_ENDIF
+    };
+    assert_eq!(nested.execution_path_count(), 4);
+
+    // Every call of a subscript counts
+    let repeated = single * 10;
+    assert_eq!(repeated.execution_path_count(), 1024);
+    assert_eq!((repeated * 7).execution_path_count(), usize::MAX);
+}
+
+fn hash_gadget() -> Script {
+    script! {
+        for _ in 0..50 {
+            OP_SHA256
+            { vec![0x42u8; 32] }
+            OP_EQUALVERIFY
+        }
+    }
+}
+
+#[test]
+fn test_compact_repr() {
+    let mut script = script! {
+        OP_DUP
+    };
+    for i in 0..10 {
+        script = script.push_env_script(script! {
+            { i }
+            OP_DROP
+            { hash_gadget() }
+        });
+    }
+    let compact = script.to_compact_repr();
+    // The gadget and its loop are stored once
+    assert_eq!(compact.nodes.len(), 1 + 10 + 2);
+    assert_eq!(compact.scripts.len(), 2 + 10);
+
+    let bytes = compact.to_bytes();
+    assert!(bytes.len() < script.len() / 5);
+    assert_eq!(CompactScript::from_bytes(&bytes).unwrap(), compact);
+
+    let restored = Script::from_compact_repr(compact).unwrap();
+    assert_eq!(restored.len(), script.len());
+    assert_eq!(restored.structure_hash(), script.structure_hash());
+    for position in [0, 1, 3, script.len() - 1] {
+        assert_eq!(restored.debug_info(position), script.debug_info(position));
+    }
+    assert_eq!(restored.compile(), script.compile());
+}
+
+#[test]
+fn test_compact_repr_malformed_bytes() {
+    let bytes = script! { OP_ADD { hash_gadget() } }
+        .to_compact_repr()
+        .to_bytes();
+    assert!(CompactScript::from_bytes(&bytes[..bytes.len() - 1]).is_none());
+    assert!(CompactScript::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_none());
+    assert!(CompactScript::from_bytes(&[]).is_none());
+}
+
+#[test]
+fn test_compact_repr_invalid() {
+    let compact = script! { OP_ADD { hash_gadget() } }.to_compact_repr();
+    assert!(compact.is_valid());
+
+    let empty = CompactScript {
+        scripts: vec![],
+        nodes: vec![],
+    };
+    assert!(Script::from_compact_repr(empty).is_none());
+
+    let mut missing_script = compact.clone();
+    missing_script.scripts.pop();
+    assert!(Script::from_compact_repr(missing_script).is_none());
+
+    // A call to the root node would be a cycle
+    let mut cycle = compact.clone();
+    let root = cycle.nodes.len() as u32 - 1;
+    cycle.nodes[root as usize]
+        .blocks
+        .push(CompactBlock::Call(root));
+    assert!(Script::from_compact_repr(cycle.clone()).is_none());
+    assert!(CompactScript::from_bytes(&cycle.to_bytes()).is_none());
+}
+
+#[test]
+fn test_p2wsh_witness_script() {
+    let script = script! {
+        OP_SHA256
+        { vec![0x42u8; 32] }
+        OP_EQUAL
+    };
+    let compiled = script.clone().compile();
+    let p2wsh = script.into_p2wsh_witness_script();
+
+    assert_eq!(p2wsh.witness_script_bytes(), compiled.to_bytes());
+    assert_eq!(
+        p2wsh.address(Network::Bitcoin),
+        Address::p2wsh(&compiled, Network::Bitcoin)
+    );
+    let locking_script = p2wsh.locking_script().compile();
+    assert_eq!(locking_script.len(), 34);
+    assert!(locking_script.is_p2wsh());
+    assert_eq!(
+        locking_script,
+        Address::p2wsh(&compiled, Network::Bitcoin).script_pubkey()
+    );
+}
+
+#[test]
+fn test_max_if_depth() {
+    assert_eq!(script! { OP_ADD }.max_if_depth(), 0);
+
+    let nested = script! {
+        OP_IF
+            OP_NOTIF
+                OP_IF
+                    OP_ADD
+                OP_ENDIF
+            OP_ENDIF
+        OP_ELSE
+            OP_IF
+            OP_ENDIF
+        OP_ENDIF
+    };
+    assert_eq!(nested.max_if_depth(), 3);
+
+    let script = script! {
+        OP_IF
+            OP_IF
+                { nested.clone() }
+            OP_ENDIF
+        OP_ENDIF
+        // Siblings do not add up
+        OP_IF
+        OP_ENDIF
+        { nested }
+    };
+    assert_eq!(script.max_if_depth(), 5);
+}
+
+fn documented_gadget() -> Script {
+    script! {
+        //! Checks that the top stack item is smaller than 10.
+        //! Fails the script otherwise.
+        OP_DUP
+        10
+        OP_LESSTHAN
+        OP_VERIFY
+    }
+}
+
+fn documented_caller() -> Script {
+    script! {
+        //! Duplicates the small item
+        { documented_gadget() }
+        OP_DUP
+    }
+}
+
+#[test]
+fn test_doc_comments() {
+    let gadget = documented_gadget();
+    assert_eq!(
+        gadget.doc.as_deref(),
+        Some("Checks that the top stack item is smaller than 10.\nFails the script otherwise.")
+    );
+    assert_eq!(gadget.len(), 4);
+    assert_eq!(script! { OP_ADD }.doc, None);
+
+    let script = script! {
+        //! Adds 1 if the item is small
+        OP_1
+        { documented_gadget() }
+        OP_ADD
+    };
+    let restored = Script::from_compact_repr(
+        CompactScript::from_bytes(&script.to_compact_repr().to_bytes()).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(restored.doc.as_deref(), Some("Adds 1 if the item is small"));
+    let called = restored
+        .depth_first_iter()
+        .find_map(|item| match item {
+            DfsItem::EnterCall { id, .. } => Some(id),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(restored.get_structured_script(&called).doc, gadget.doc);
+
+    // A call as the first item is inlined, the doc of the caller is kept in front
+    let script = documented_caller();
+    assert_eq!(
+        script.doc.as_deref(),
+        Some(
+            "Duplicates the small item\nChecks that the top stack item is smaller than 10.\n\
+             Fails the script otherwise."
+        )
+    );
+    assert_eq!(script.len(), 5);
+}
+
+/// Checks the small item twice
+#[script_doc]
+fn attributed_gadget(times: usize) -> Script {
+    if times == 0 {
+        return script! {};
+    }
+    script! {
+        for _ in 0..times {
+            { documented_gadget() }
+        }
+    }
+}
+
+#[test]
+fn test_script_doc_attribute() {
+    let script = attributed_gadget(2);
+    assert_eq!(
+        script.doc.as_deref(),
+        Some(
+            "Checks the small item twice\nChecks that the top stack item is smaller than 10.\n\
+             Fails the script otherwise."
+        )
+    );
+    assert_eq!(script.len(), 8);
+    assert_eq!(
+        attributed_gadget(0).doc.as_deref(),
+        Some("Checks the small item twice")
+    );
+}
+
+fn gadget() -> Script {
+    script! { OP_ADD }
+}
+
+fn gadget_caller() -> Script {
+    script! {
+        { gadget() }
+        OP_ADD
+    }
+}
+
+fn iffed(flag: bool) -> Script {
+    script! {
+        if flag {
+            OP_ADD
+        } else {
+            OP_SUB
+        }
+    }
+}
+
+fn looped() -> Script {
+    script! {
+        for _ in 0..2 {
+            OP_ADD
+        }
+    }
+}
+
+fn matched(n: u8) -> Script {
+    script! {
+        match n {
+            0 => { OP_ADD }
+            _ => { OP_SUB }
+        }
+    }
+}
+
+#[test]
+fn test_debug_info_of_inlined_scripts() {
+    // The first script is inlined, so its identifier is reported and not the caller's
+    let script = gadget_caller();
+    assert!(script.debug_info(0).contains("gadget"));
+    assert!(!script.debug_info(0).contains("gadget_caller"));
+
+    // The placeholder identifiers of the macro do not show up
+    for (script, name) in [
+        (iffed(true), "iffed"),
+        (iffed(false), "iffed"),
+        (looped(), "looped"),
+        (matched(0), "matched"),
+        (matched(1), "matched"),
+    ] {
+        for position in 0..script.len() {
+            let debug_info = script.debug_info(position);
+            assert!(debug_info.contains(name), "{}", debug_info);
+            assert!(
+                !debug_info
+                    .split_whitespace()
+                    .any(|word| ["if", "for", "match"].contains(&word)),
+                "{}",
+                debug_info
+            );
+        }
+    }
+}
+
+#[test]
+fn test_unique_opcodes() {
+    let script = script! {
+        for _ in 0..10 {
+            OP_ADD
+        }
+    };
+    assert_eq!(script.count_unique_opcodes(), 1);
+
+    let p2pkh = script! {
+        OP_DUP
+        OP_HASH160
+        { vec![0x42u8; 20] }
+        OP_EQUALVERIFY
+        OP_CHECKSIG
+    };
+    assert_eq!(
+        p2pkh.unique_opcodes(),
+        vec![
+            OP_PUSHBYTES_20,
+            OP_DUP,
+            OP_EQUALVERIFY,
+            OP_HASH160,
+            OP_CHECKSIG
+        ]
+    );
+    assert_eq!(p2pkh.count_unique_opcodes(), 5);
+}
+
+fn compute_scripts() -> Vec<Script> {
+    (0..5).map(|i| script! { { i } OP_ADD }).collect()
+}
+
+#[test]
+fn test_spread() {
+    let script = script! {
+        spread!([script! { OP_1 }, script! { OP_2 }])
+    };
+    assert_eq!(script.compile(), script! { OP_1 OP_2 }.compile());
+
+    let script = script! {
+        OP_DUP
+        spread!(compute_scripts())
+        spread!(Vec::<Script>::new())
+    };
+    let expected = script! {
+        OP_DUP
+        for s in compute_scripts() {
+            { s }
+        }
+    };
+    assert_eq!(script.compile(), expected.compile());
+}
+
+fn called_ids(script: &Script) -> Vec<ScriptId> {
+    script
+        .blocks
+        .iter()
+        .filter_map(|block| match block {
+            Block::Call(id) => Some(*id),
+            Block::Script(_) => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_dependency_graph() {
+    let d = script! { OP_ADD OP_DUP };
+    let b = script! { OP_1 { d.clone() } OP_2 };
+    let c = script! { OP_3 { d.clone() } { d } };
+    let a = script! { OP_DUP { b.clone() } { c.clone() } };
+
+    let a_calls = called_ids(&a);
+    assert_eq!(a_calls.len(), 2);
+    let (b_id, c_id) = (a_calls[0], a_calls[1]);
+    let b_calls = called_ids(a.get_structured_script(&b_id));
+    let c_calls = called_ids(a.get_structured_script(&c_id));
+    let d_id = b_calls[0];
+    // Both calls of D in C are the same dependency
+    assert_eq!(c_calls, vec![d_id, d_id]);
+
+    let graph = a.dependency_graph();
+    assert_eq!(graph.len(), 4);
+    let root = graph
+        .iter()
+        .find(|(_, calls)| **calls == vec![b_id, c_id])
+        .map(|(id, _)| *id)
+        .unwrap();
+    assert!(!graph.values().any(|calls| calls.contains(&root)));
+    assert_eq!(graph[&b_id], vec![d_id]);
+    assert_eq!(graph[&c_id], vec![d_id]);
+    assert!(graph[&d_id].is_empty());
+}
+
+#[test]
+fn test_asm_roundtrip() {
+    let inner = script! { OP_DUP OP_HASH160 <vec![0xab; 20]> OP_EQUALVERIFY OP_CHECKSIG };
+    let scripts = vec![
+        script! {},
+        script! { OP_0 OP_1 OP_16 -1 17 -300 255 1000000 },
+        script! { OP_IF { inner.clone() } OP_ELSE 100 OP_CSV OP_DROP OP_ENDIF },
+        script! { <vec![7; 100]> <vec![8; 300]> OP_2DROP },
+        script! {
+            for i in 0..20 {
+                { i } OP_ADD
+            }
+            { inner }
+        },
+    ];
+    for script in scripts {
+        let asm = script.to_asm_string();
+        let parsed = Script::try_from(asm.as_str()).unwrap();
+        assert_eq!(parsed.compile(), script.compile(), "{}", asm);
+    }
+}
+
+#[test]
+fn test_asm_doc_comments() {
+    let script = script! {
+        //! Adds 1 if the item is small
+        OP_1
+        { documented_gadget() }
+        OP_ADD
+    };
+    let asm = script.to_asm_string();
+    assert_eq!(
+        asm,
+        "// Adds 1 if the item is small\n\
+         OP_PUSHNUM_1\n\
+         // Checks that the top stack item is smaller than 10.\n\
+         // Fails the script otherwise.\n\
+         OP_DUP OP_PUSHNUM_10 OP_LESSTHAN OP_VERIFY\n\
+         OP_ADD"
+    );
+    let parsed = Script::try_from(asm.as_str()).unwrap();
+    assert_eq!(parsed.compile(), script.compile());
+
+    // Scripts without doc print the asm of the compiled script
+    let script = script! { OP_1 { script! { OP_DUP OP_ADD } } OP_ADD };
+    assert_eq!(
+        script.to_asm_string(),
+        script.clone().compile().to_asm_string()
+    );
+
+    let err = Script::try_from("// OP_FOO\nOP_DUP OP_FOO // OP_BAR").unwrap_err();
+    assert_eq!((err.position, err.token.as_str()), (17, "OP_FOO"));
+}
+
+#[test]
+fn test_asm_parse_errors() {
+    let parsed = Script::try_from("OP_DUP 0102 OP_PUSHBYTES_1 00").unwrap();
+    assert_eq!(
+        parsed.compile().to_bytes(),
+        vec![OP_DUP.to_u8(), 0x02, 0x01, 0x02, 0x01, 0x00]
+    );
+
+    // Non-minimal pushes are re-encoded minimally
+    for (asm, opcode) in [
+        ("OP_PUSHBYTES_1 05", OP_PUSHNUM_5),
+        ("05", OP_PUSHNUM_5),
+        ("OP_PUSHBYTES_1 10", OP_PUSHNUM_16),
+        ("81", OP_PUSHNUM_NEG1),
+        ("OP_PUSHDATA1 81", OP_PUSHNUM_NEG1),
+    ] {
+        let parsed = Script::try_from(asm).unwrap();
+        assert_eq!(parsed.compile().to_bytes(), vec![opcode.to_u8()], "{}", asm);
+    }
+    let parsed = Script::try_from("OP_PUSHDATA1 0102").unwrap();
+    assert_eq!(parsed.compile().to_bytes(), vec![0x02, 0x01, 0x02]);
+    let err = Script::try_from("OP_PUSHDATA1 ").unwrap_err();
+    assert_eq!(err.reason, "missing push data");
+
+    let err = Script::try_from("OP_DUP OP_FOO").unwrap_err();
+    assert_eq!((err.position, err.token.as_str()), (7, "OP_FOO"));
+    let err = Script::try_from("OP_PUSHBYTES_2 01").unwrap_err();
+    assert_eq!((err.position, err.token.as_str()), (15, "01"));
+    let err = Script::try_from("OP_ADD OP_PUSHBYTES_2").unwrap_err();
+    assert_eq!(err.reason, "missing push data");
+    let err = Script::try_from("OP_ADD 0g").unwrap_err();
+    assert_eq!(err.reason, "invalid hex");
+}
+
+fn stable_id_script() -> Script {
+    let inner = script! { OP_DUP OP_ADD };
+    script! { OP_1 { inner } 1000 OP_EQUAL }
+}
+
+// Hardcoded so that the tests also check that the id is the same in every process.
+const STABLE_ID: ScriptId = ScriptId(10010205986506314924);
+
+#[test]
+fn test_script_id_is_stable() {
+    let script = stable_id_script();
+    assert_eq!(script.id(), stable_id_script().id());
+    assert_eq!(script.id(), STABLE_ID);
+}
+
+#[test]
+fn test_script_id_is_stable_in_other_test() {
+    assert_eq!(stable_id_script().id(), STABLE_ID);
+    let called = called_ids(&stable_id_script())[0];
+    assert_eq!(called, script! { OP_DUP OP_ADD }.id());
+}
+
+#[test]
+fn test_compiled_eq() {
+    let flat = script! { OP_DUP OP_ADD 1000 OP_EQUAL };
+    let inner = script! { OP_ADD 1000 };
+    let nested = script! { OP_DUP { inner } OP_EQUAL };
+    assert_eq!(flat.blocks.len(), 1);
+    assert_eq!(nested.blocks.len(), 3);
+    assert!(flat.compiled_eq(&nested));
+    assert!(nested.compiled_eq(&flat));
+
+    assert!(!flat.compiled_eq(&script! { OP_DUP OP_ADD 1001 OP_EQUAL }));
+    assert!(!flat.compiled_eq(&script! { OP_DUP OP_ADD }));
+}
+
+#[test]
+fn test_push_raw_opcode() {
+    // OP_CAT of BIP 347 and an opcode byte that is undefined today
+    let script = Script::new("raw")
+        .push_opcode(OP_DUP)
+        .push_raw_opcode(0x7e)
+        .push_raw_opcode(0xbb);
+    assert_eq!(script.len(), 3);
+    assert_eq!(
+        script.compile().to_bytes(),
+        vec![OP_DUP.to_u8(), 0x7e, 0xbb]
+    );
+}
+
+#[test]
+#[should_panic(expected = "Not an opcode without data")]
+fn test_push_raw_opcode_rejects_pushes() {
+    let _ = Script::new("raw").push_raw_opcode(0x14);
+}
+
+#[test]
+fn test_mut_push() {
+    let inner = script! { OP_ADD 1000 };
+    let mut mutated = Script::new("mutated");
+    let mut consumed = Script::new("consumed");
+    for i in 0..20 {
+        mutated
+            .mut_push_opcode(OP_DUP)
+            .mut_push_int(i)
+            .mut_push_slice([i as u8; 3])
+            .mut_push_env_script(inner.clone());
+        consumed = consumed
+            .push_opcode(OP_DUP)
+            .push_int(i)
+            .push_slice([i as u8; 3])
+            .push_env_script(inner.clone());
+    }
+    assert_eq!(mutated.len(), consumed.len());
+    assert_eq!(mutated.debug_identifier, "mutated");
+    assert_eq!(mutated.compile(), consumed.compile());
+}
+
 #[test]
 fn test_performance_loop() {
     let mut nested_script = script! {
@@ -177,6 +1097,87 @@ fn test_performance_loop() {
     assert_eq!(compiled_script.as_bytes()[5_000_000 - 1], 147)
 }
 
+// Finds the block of the position by walking through all blocks like debug_info did
+// before it used a binary search.
+fn debug_info_linear(script: &Script, position: usize) -> String {
+    let mut start = 0;
+    for block in &script.blocks {
+        let (len, called) = match block {
+            Block::Call(id) => {
+                let called = script.get_structured_script(id);
+                (called.len(), Some(called))
+            }
+            Block::Script(buf) => (buf.len(), None),
+        };
+        if position < start + len {
+            return match called {
+                Some(called) => debug_info_linear(called, position - start),
+                None => script.debug_identifier.clone(),
+            };
+        }
+        start += len;
+    }
+    panic!("Position {} is out of bounds", position);
+}
+
+fn many_blocks() -> Script {
+    let mut script = Script::new("blocks");
+    for _ in 0..1000 {
+        script = script
+            .push_opcode(OP_NOP)
+            .push_env_script(script_named!("called", OP_ADD OP_DUP));
+    }
+    assert_eq!(script.blocks.len(), 2000);
+    script
+}
+
+#[test]
+fn test_debug_info_binary_search() {
+    let mut script = many_blocks();
+    let linear: Vec<String> = (0..script.len())
+        .map(|position| debug_info_linear(&script, position))
+        .collect();
+    let binary: Vec<String> = (0..script.len())
+        .map(|position| script.debug_info(position))
+        .collect();
+    assert_eq!(linear, binary);
+    assert!(binary[2].contains("called") && binary[3] == "blocks");
+
+    // Blocks that are added directly are found without the binary search
+    let len = script.len();
+    let added = script_named!("added", OP_ADD);
+    script.blocks.push(Block::Call(added.id()));
+    script
+        .blocks
+        .push(Block::Script(script! { OP_ADD }.compile()));
+    script.add_structured_script(added.id(), added);
+    assert_eq!(script.debug_info(len - 1), binary[len - 1]);
+    assert!(script.debug_info(len).contains("added"));
+    assert_eq!(script.debug_info(len + 1), "blocks");
+}
+
+// Compares debug_info with a linear search through the blocks. Run it with
+// `cargo test --release --test test -- --ignored bench_debug_info --nocapture`.
+#[test]
+#[ignore]
+fn bench_debug_info() {
+    let script = many_blocks();
+    let start = Instant::now();
+    for position in 0..script.len() {
+        debug_info_linear(&script, position);
+    }
+    let linear_time = start.elapsed();
+    let start = Instant::now();
+    for position in 0..script.len() {
+        script.debug_info(position);
+    }
+    let binary_time = start.elapsed();
+    println!(
+        "binary search took {:?}, linear search {:?}",
+        binary_time, linear_time
+    );
+}
+
 #[test]
 fn test_performance_no_macro() {
     let mut builder = bitcoin::script::Builder::new();
@@ -303,3 +1304,64 @@ fn test_push_witness() {
         reference_script.compile().as_bytes()
     );
 }
+
+#[test]
+fn test_depth_first_iter() {
+    let inner = script! { OP_ADD };
+    let middle = script! {
+        OP_DUP
+        { inner.clone() }
+    };
+    let script = script! {
+        OP_1
+        { middle }
+        { inner }
+        OP_2
+    };
+
+    let items: Vec<DfsItem> = script.depth_first_iter().collect();
+    let mut depth = 0;
+    let mut shape = Vec::new();
+    for item in &items {
+        match item {
+            DfsItem::EnterCall { depth: d, .. } => {
+                depth += 1;
+                assert_eq!(*d, depth);
+                shape.push(format!("enter{}", d));
+            }
+            DfsItem::ScriptBuf { buf, .. } => shape.push(buf.to_asm_string()),
+            DfsItem::ExitCall { depth: d, .. } => {
+                assert_eq!(*d, depth);
+                depth -= 1;
+                shape.push(format!("exit{}", d));
+            }
+        }
+    }
+    assert_eq!(
+        shape,
+        vec![
+            "OP_PUSHNUM_1",
+            "enter1",
+            "OP_DUP",
+            "enter2",
+            "OP_ADD",
+            "exit2",
+            "exit1",
+            "enter1",
+            "OP_ADD",
+            "exit1",
+            "OP_PUSHNUM_2"
+        ]
+    );
+
+    // Concatenating the visited buffers yields the compiled script.
+    let flattened: Vec<u8> = items
+        .iter()
+        .filter_map(|item| match item {
+            DfsItem::ScriptBuf { buf, .. } => Some(buf.as_bytes().to_vec()),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    assert_eq!(flattened, script.compile().to_bytes());
+}
@@ -0,0 +1,551 @@
+use bitcoin::hashes::Hash;
+use bitcoin::opcodes::all::OP_ADD;
+use bitcoin::secp256k1::{Secp256k1, SecretKey};
+use bitcoin::{PublicKey, ScriptBuf, XOnlyPublicKey};
+use bitcoin_script::validation::{
+    ConditionProducer, Incompatibility, IncompatibilityKind, MinimalIfWarning, NetworkRule,
+    NetworkRuleViolation, ScriptContext, ScriptContextError, ScriptType, ScriptValidationContext,
+    SigBudget,
+};
+use bitcoin_script::{script, Script};
+
+#[test]
+fn test_valid_script_has_no_violations() {
+    let script = script! {
+        OP_DUP
+        OP_HASH160
+        { vec![0x42u8; 20] }
+        OP_EQUALVERIFY
+        OP_CHECKSIG
+    };
+    for context in [
+        ScriptValidationContext::Legacy,
+        ScriptValidationContext::P2SH,
+        ScriptValidationContext::P2WSH,
+        ScriptValidationContext::P2TR,
+    ] {
+        assert!(script.validate_network_rules(context).is_empty());
+    }
+}
+
+#[test]
+fn test_script_size_violations() {
+    let script = script! {
+        for _ in 0..150 {
+            { vec![0x01u8; 70] }
+            OP_DROP
+        }
+    };
+    assert_eq!(script.len(), 150 * 72);
+
+    assert_eq!(
+        script.validate_network_rules(ScriptValidationContext::Legacy),
+        vec![NetworkRuleViolation {
+            rule: NetworkRule::ScriptSize,
+            value: 10_800,
+            limit: 10_000
+        }]
+    );
+    assert_eq!(
+        script.validate_network_rules(ScriptValidationContext::P2SH),
+        vec![NetworkRuleViolation {
+            rule: NetworkRule::ScriptSize,
+            value: 10_800,
+            limit: 520
+        }]
+    );
+    assert!(script
+        .validate_network_rules(ScriptValidationContext::P2TR)
+        .is_empty());
+}
+
+#[test]
+fn test_push_size_violation() {
+    let script = script! {
+        { vec![0x01u8; 521] }
+        OP_DROP
+        { vec![0x01u8; 520] }
+    };
+    assert_eq!(
+        script.validate_network_rules(ScriptValidationContext::P2TR),
+        vec![NetworkRuleViolation {
+            rule: NetworkRule::PushSize,
+            value: 521,
+            limit: 520
+        }]
+    );
+}
+
+#[test]
+fn test_op_count_violation() {
+    let script = script! {
+        for _ in 0..202 {
+            OP_NOP
+        }
+        // Pushes do not count
+        for _ in 0..16 {
+            OP_1
+        }
+    };
+    let violations = script.validate_network_rules(ScriptValidationContext::P2WSH);
+    assert_eq!(
+        violations,
+        vec![NetworkRuleViolation {
+            rule: NetworkRule::OpCount,
+            value: 202,
+            limit: 201
+        }]
+    );
+    assert_eq!(
+        violations[0].to_string(),
+        "opcode count of 202 exceeds the limit of 201"
+    );
+}
+
+#[test]
+fn test_sigop_violations() {
+    // 15 keys are counted exactly, an unknown key count is counted as 20
+    let script = script! {
+        OP_CHECKSIG
+        OP_15
+        OP_CHECKMULTISIG
+    };
+    assert_eq!(
+        script.validate_network_rules(ScriptValidationContext::P2SH),
+        vec![NetworkRuleViolation {
+            rule: NetworkRule::SigOps,
+            value: 16,
+            limit: 15
+        }]
+    );
+
+    let script = script! {
+        OP_DROP
+        OP_CHECKMULTISIGVERIFY
+    };
+    assert_eq!(
+        script.validate_network_rules(ScriptValidationContext::P2SH)[0].value,
+        20
+    );
+    // Bare scriptPubKeys count every CHECKMULTISIG as 20
+    let script = script! {
+        for _ in 0..201 {
+            OP_1
+            OP_CHECKMULTISIG
+        }
+    };
+    assert_eq!(
+        script.validate_network_rules(ScriptValidationContext::Legacy),
+        vec![NetworkRuleViolation {
+            rule: NetworkRule::SigOps,
+            value: 4020,
+            limit: 4000
+        }]
+    );
+    assert!(script
+        .validate_network_rules(ScriptValidationContext::P2WSH)
+        .is_empty());
+}
+
+#[test]
+fn test_op_return_payload_violation() {
+    let script = script! {
+        OP_RETURN
+        { vec![0x01u8; 40] }
+        { vec![0x02u8; 41] }
+    };
+    assert_eq!(
+        script.validate_network_rules(ScriptValidationContext::Legacy),
+        vec![NetworkRuleViolation {
+            rule: NetworkRule::OpReturnPayload,
+            value: 81,
+            limit: 80
+        }]
+    );
+    // The payload limit only applies to outputs
+    assert!(script
+        .validate_network_rules(ScriptValidationContext::P2WSH)
+        .is_empty());
+}
+
+fn public_key(byte: u8) -> PublicKey {
+    let secret_key = SecretKey::from_slice(&[byte; 32]).unwrap();
+    PublicKey::new(secret_key.public_key(&Secp256k1::new()))
+}
+
+#[test]
+fn test_classify_script_type() {
+    let key = public_key(1);
+    let wpubkey_hash = key.wpubkey_hash().unwrap();
+    let script_hash = ScriptBuf::new().script_hash();
+    let x_only_key = XOnlyPublicKey::from(key.inner);
+
+    let p2pk = Script::new("")
+        .push_key(&key)
+        .push_opcode(bitcoin::opcodes::all::OP_CHECKSIG);
+    assert_eq!(p2pk.classify_script_type(), ScriptType::P2PK);
+
+    let p2pkh = script! {
+        OP_DUP
+        OP_HASH160
+        { key.pubkey_hash().as_byte_array().to_vec() }
+        OP_EQUALVERIFY
+        OP_CHECKSIG
+    };
+    assert_eq!(p2pkh.classify_script_type(), ScriptType::P2PKH);
+
+    let p2sh = script! {
+        OP_HASH160
+        { script_hash.as_byte_array().to_vec() }
+        OP_EQUAL
+    };
+    assert_eq!(p2sh.classify_script_type(), ScriptType::P2SH);
+
+    let p2wpkh = script! {
+        OP_0
+        { AsRef::<[u8]>::as_ref(&wpubkey_hash).to_vec() }
+    };
+    assert_eq!(p2wpkh.classify_script_type(), ScriptType::P2WPKH);
+
+    let p2wsh = script! {
+        OP_0
+        { vec![0x42u8; 32] }
+    };
+    assert_eq!(p2wsh.classify_script_type(), ScriptType::P2WSH);
+
+    let p2tr = script! {
+        OP_1
+        { x_only_key }
+    };
+    assert_eq!(p2tr.classify_script_type(), ScriptType::P2TR);
+
+    let op_return = script! {
+        OP_RETURN
+        { vec![0x42u8; 10] }
+    };
+    assert_eq!(op_return.classify_script_type(), ScriptType::OpReturn);
+
+    let multisig = script! {
+        OP_2
+        { key }
+        { public_key(2) }
+        { public_key(3) }
+        OP_3
+        OP_CHECKMULTISIG
+    };
+    assert_eq!(multisig.classify_script_type(), ScriptType::Multisig);
+
+    for script in [p2pk, p2pkh, p2sh, p2wpkh, p2wsh, p2tr, op_return, multisig] {
+        assert!(script.is_standard_output());
+    }
+
+    let script = script! {
+        OP_1
+        OP_ADD
+        OP_2
+        OP_EQUAL
+    };
+    assert_eq!(script.classify_script_type(), ScriptType::NonStandard);
+    assert!(!script.is_standard_output());
+}
+
+fn if_block() -> Script {
+    script! {
+        OP_IF
+            OP_1
+        OP_ELSE
+            OP_0
+        OP_ENDIF
+    }
+}
+
+#[test]
+fn test_minimal_if_compliant() {
+    let script = script! {
+        OP_EQUAL
+        { if_block() }
+        OP_1
+        OP_NOTIF
+            OP_0
+            OP_IF
+            OP_ENDIF
+        OP_ENDIF
+        OP_CHECKSIG
+        OP_NOTIF
+        OP_ENDIF
+    };
+    assert!(script.check_minimal_if().is_empty());
+}
+
+#[test]
+fn test_minimal_if_arithmetic_condition() {
+    let script = script! {
+        OP_ADD
+        { if_block() }
+        OP_2
+        OP_NOTIF
+        OP_ENDIF
+    };
+    let warnings = script.check_minimal_if();
+    assert_eq!(warnings.len(), 2);
+    assert_eq!(
+        warnings[0],
+        MinimalIfWarning {
+            position: 1,
+            debug_identifier: script.debug_info(1),
+            producer: ConditionProducer::Opcode(OP_ADD)
+        }
+    );
+    assert!(warnings[0].debug_identifier.contains("if_block"));
+    assert_eq!(warnings[1].position, 7);
+    assert_eq!(
+        warnings[1].producer,
+        ConditionProducer::Opcode(bitcoin::opcodes::all::OP_PUSHNUM_2)
+    );
+}
+
+#[test]
+fn test_minimal_if_unknown_condition() {
+    // The condition is a script input or was moved around on the stack
+    let script = script! {
+        OP_IF
+        OP_ENDIF
+        OP_DUP
+        OP_IF
+        OP_ENDIF
+    };
+    let warnings = script.check_minimal_if();
+    assert_eq!(warnings.len(), 2);
+    assert_eq!(warnings[0].position, 0);
+    assert_eq!(warnings[1].position, 3);
+    assert!(warnings
+        .iter()
+        .all(|warning| warning.producer == ConditionProducer::Unknown));
+}
+
+#[test]
+fn test_validate_for_opcodes() {
+    let common = script! {
+        OP_DUP
+        OP_CHECKSIGVERIFY
+        OP_NOP1
+        OP_RETURN
+    };
+    for context in [
+        ScriptContext::Legacy,
+        ScriptContext::P2SH,
+        ScriptContext::P2WSH,
+        ScriptContext::P2TR,
+    ] {
+        assert_eq!(common.validate_for(context), Ok(()));
+    }
+
+    let multisig = script! {
+        OP_1
+        OP_CHECKMULTISIG
+    };
+    assert_eq!(multisig.validate_for(ScriptContext::P2WSH), Ok(()));
+    assert_eq!(
+        multisig.validate_for(ScriptContext::P2TR),
+        Err(vec![ScriptContextError::InvalidOpcode {
+            opcode: bitcoin::opcodes::all::OP_CHECKMULTISIG,
+            position: 1,
+            context: ScriptContext::P2TR
+        }])
+    );
+
+    let checksigadd = script! {
+        OP_0
+        OP_CHECKSIGADD
+    };
+    assert_eq!(checksigadd.validate_for(ScriptContext::P2TR), Ok(()));
+    let errors = checksigadd.validate_for(ScriptContext::Legacy).unwrap_err();
+    assert_eq!(
+        errors[0].to_string(),
+        "OP_CHECKSIGADD at 1 is invalid in legacy"
+    );
+
+    // Disabled opcodes are OP_SUCCESS in tapscript
+    let cat = script! {
+        OP_CAT
+    };
+    assert!(matches!(
+        cat.validate_for(ScriptContext::P2WSH).unwrap_err()[0],
+        ScriptContextError::InvalidOpcode { .. }
+    ));
+    assert_eq!(
+        cat.validate_for(ScriptContext::P2TR),
+        Err(vec![ScriptContextError::SuccessOpcode {
+            opcode: bitcoin::opcodes::all::OP_CAT,
+            position: 0
+        }])
+    );
+
+    let verif = script! {
+        OP_VERIF
+    };
+    assert!(verif.validate_for(ScriptContext::P2TR).is_err());
+}
+
+#[test]
+fn test_validate_for_limits() {
+    let script = script! {
+        for _ in 0..202 {
+            OP_NOP
+        }
+        { vec![0x01u8; 521] }
+    };
+    let errors = script.validate_for(ScriptContext::P2WSH).unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(
+        errors[1],
+        ScriptContextError::Limit {
+            violation: NetworkRuleViolation {
+                rule: NetworkRule::OpCount,
+                value: 202,
+                limit: 201
+            },
+            context: ScriptContext::P2WSH
+        }
+    );
+
+    // Only the push size limit remains in tapscript
+    let errors = script.validate_for(ScriptContext::P2TR).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(
+        &errors[0],
+        ScriptContextError::Limit { violation, .. } if violation.rule == NetworkRule::PushSize
+    ));
+
+    let script = script! {
+        for _ in 0..150 {
+            { vec![0x01u8; 70] }
+            OP_DROP
+        }
+    };
+    assert!(script.validate_for(ScriptContext::Legacy).is_err());
+    assert_eq!(script.validate_for(ScriptContext::P2TR), Ok(()));
+    // A redeem script has to fit into a single push
+    let script = script! {
+        for _ in 0..10 {
+            { vec![0x01u8; 59] }
+            OP_DROP
+        }
+    };
+    assert_eq!(script.validate_for(ScriptContext::Legacy), Ok(()));
+    let errors = script.validate_for(ScriptContext::P2SH).unwrap_err();
+    assert_eq!(
+        errors[0].to_string(),
+        "script size of 610 exceeds the limit of 520 in p2sh"
+    );
+}
+
+#[test]
+fn test_sig_budget() {
+    let script = script! {
+        OP_CHECKSIGVERIFY
+        OP_0
+        OP_CHECKSIGADD
+        OP_CHECKSIG
+    };
+    let budget = script.sig_budget();
+    assert_eq!(
+        budget,
+        SigBudget {
+            sig_ops: 3,
+            required_weight: 150,
+            available_weight: 54
+        }
+    );
+    assert_eq!(budget.missing_witness_weight(), 96);
+    assert!(!budget.is_affordable_with(95));
+    assert!(budget.is_affordable_with(96));
+}
+
+#[test]
+fn test_sig_budget_worst_case_branch() {
+    let script = script! {
+        OP_CHECKSIGVERIFY
+        OP_IF
+            OP_CHECKSIGVERIFY
+        OP_ELSE
+            OP_CHECKSIGVERIFY
+            OP_NOTIF
+                OP_CHECKSIGVERIFY
+                OP_CHECKSIGVERIFY
+            OP_ENDIF
+        OP_ENDIF
+        OP_CHECKSIG
+    };
+    let budget = script.sig_budget();
+    assert_eq!(budget.sig_ops, 5);
+    assert_eq!(budget.required_weight, 250);
+
+    let script = script! {
+        OP_IF
+            OP_CHECKSIG
+            OP_CHECKSIG
+        OP_ELSE
+            OP_CHECKSIG
+        OP_ENDIF
+    };
+    assert_eq!(script.sig_budget().sig_ops, 2);
+    assert_eq!(script.sig_budget().missing_witness_weight(), 44);
+}
+
+fn multisig_gadget() -> Script {
+    script! {
+        OP_2
+        { vec![0x02u8; 33] }
+        { vec![0x03u8; 33] }
+        OP_2
+        OP_CHECKMULTISIG
+    }
+}
+
+#[test]
+fn test_tapscript_incompatibilities() {
+    let script = script! {
+        OP_DUP
+        OP_CODESEPARATOR
+        OP_IF
+            { multisig_gadget() }
+            OP_CODESEPARATOR
+        OP_ENDIF
+        OP_CAT
+    };
+    let incompatibilities = script.tapscript_incompatibilities();
+    assert_eq!(incompatibilities.len(), 3);
+
+    // OP_DUP OP_CODESEPARATOR OP_IF OP_2 <33 bytes> <33 bytes> OP_2 OP_CHECKMULTISIG
+    let position = 3 + 1 + 34 + 34 + 1;
+    assert_eq!(
+        incompatibilities[0],
+        Incompatibility {
+            position,
+            debug_identifier: script.debug_info(position),
+            opcode: bitcoin::opcodes::all::OP_CHECKMULTISIG,
+            kind: IncompatibilityKind::CheckMultisig
+        }
+    );
+    assert!(incompatibilities[0]
+        .debug_identifier
+        .contains("multisig_gadget"));
+    assert_eq!(
+        script.clone().compile().as_bytes()[position],
+        bitcoin::opcodes::all::OP_CHECKMULTISIG.to_u8()
+    );
+    assert_eq!(
+        incompatibilities[1].kind,
+        IncompatibilityKind::CodeSeparatorInBranch
+    );
+    assert_eq!(incompatibilities[1].position, position + 1);
+    assert_eq!(
+        incompatibilities[2].kind,
+        IncompatibilityKind::SuccessOpcode
+    );
+
+    assert!(script! { OP_CODESEPARATOR OP_CHECKSIGADD }
+        .tapscript_incompatibilities()
+        .is_empty());
+}